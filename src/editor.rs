@@ -1,25 +1,54 @@
-use std::io::{Stdout, Write};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Stdout, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
 
 use anyhow::Result;
 use crossterm::{
     cursor::{self, SetCursorStyle},
-    event::{self, read, KeyCode},
-    style::{self, Stylize},
-    terminal, ExecutableCommand, QueueableCommand,
+    event::{self, read, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    style, terminal, ExecutableCommand, QueueableCommand,
 };
 
+use unicode_width::UnicodeWidthStr;
+
+use buffer::Buffer;
+use capabilities::Capabilities;
+use highlight::{priority, Highlights};
+use keymap::{Key, Keymap, Typed};
+use layout::{fit, fit_columns, pad_columns, Layout};
+use options::Options;
+
+mod action;
+mod bindings;
+mod buffer;
+mod capabilities;
+mod command;
+mod config;
+mod highlight;
+mod indent;
+mod input;
+mod keymap;
+mod layout;
+mod markup;
+mod motion;
+mod options;
+mod project;
+mod script;
+mod statusline;
+mod title;
+mod undo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Mode {
     Normal,
     Insert,
-}
-
-impl Mode {
-    pub fn get_cursor_style(&self) -> SetCursorStyle {
-        match self {
-            Self::Normal => SetCursorStyle::SteadyBlock,
-            Self::Insert => SetCursorStyle::BlinkingBar,
-        }
-    }
+    /// Typing an ex command after `:`.
+    Command,
 }
 
 impl std::fmt::Display for Mode {
@@ -27,151 +56,934 @@ impl std::fmt::Display for Mode {
         match self {
             Self::Normal => f.write_str("NORMAL"),
             Self::Insert => f.write_str("INSERT"),
+            Self::Command => f.write_str("COMMAND"),
         }
     }
 }
 
+#[derive(Debug)]
 enum Action {
     Quit,
+    /// Quits with a failure status, so that a program waiting on the editor,
+    /// such as git waiting for a commit message, can tell it was aborted.
+    Abort,
+    Cancel,
+    AppendCount(u16),
+    /// The first key of a two-key command such as `]]`.
+    BeginPrefix(char),
     ChangeMode(Mode),
     MoveUp,
     MoveDown,
     MoveLeft,
     MoveRight,
+    /// `]]` and `[[`.
+    NextSection,
+    PrevSection,
     AddChar(char),
+    /// Types `>`, closing the tag it completes in markup files.
+    CloseTag,
     NewLine,
+    DeleteBackward,
+    /// Writes the buffer, to the given file instead of its own if any.
+    Save(Option<PathBuf>),
+    /// Runs the command typed on the command line.
+    SubmitCommand(String),
+    /// Inserts text literally, as if pasted.
+    Paste(String),
+    Undo,
+    Redo,
+}
+
+/// Transient input state layered on top of the current mode. Esc and Ctrl-c
+/// unwind the innermost layer before leaving the mode itself, so anything
+/// pushed here is cancellable without further wiring.
+#[derive(Debug)]
+enum Pending {
+    Count(u16),
+    Prefix(char),
+}
+
+/// A line for the message area, shown until the next key is typed.
+struct Message {
+    text: String,
+    error: bool,
 }
 
+/// A position in the buffer: line `y` and column `x`, counted in chars.
 struct Cursor {
-    x: u16,
-    y: u16,
+    x: usize,
+    y: usize,
 }
 
 pub struct Editor {
     mode: Mode,
     stdout: Stdout,
+    buffer: Buffer,
     cursor: Cursor,
+    /// The first buffer line shown in the text area.
+    top: usize,
     size: (u16, u16),
+    caps: Capabilities,
+    /// Whether the terminal has focus; only reported by terminals that
+    /// support focus events.
+    focused: bool,
+    cwd: PathBuf,
+    project_root: Option<PathBuf>,
+    pending: Vec<Pending>,
+    message: Option<Message>,
+    /// What has been typed after `:` in command mode.
+    command_line: String,
+    options: Options,
+    highlights: Highlights,
+    keymap: Keymap,
+    /// Keys waiting to be processed, typed or produced by mappings.
+    typeahead: VecDeque<Typed>,
+    /// Keys matched so far against a mapping that is not yet complete.
+    pending_keys: Vec<Typed>,
+    /// Where typed keys are recorded, set by `record_script`.
+    script_out: Option<File>,
+    /// Set once the editor should exit, with the status to exit with.
+    quit: Option<ExitCode>,
+    /// The terminal title last set, to skip writing it when unchanged.
+    title: Option<String>,
 }
 
 impl Editor {
     pub fn new() -> Self {
+        let mut editor = Self::with_caps(Capabilities::detect());
+        editor.load_config();
+        editor.refresh_project_root();
+        editor
+    }
+
+    /// An editor with default options and no config, for a terminal that
+    /// can do what `caps` says.
+    fn with_caps(caps: Capabilities) -> Self {
         Self {
             mode: Mode::Normal,
             stdout: std::io::stdout(),
+            buffer: Buffer::default(),
             cursor: Cursor { x: 0, y: 0 },
-            size: terminal::size().unwrap(),
+            top: 0,
+            size: terminal_size(),
+            caps,
+            focused: true,
+            cwd: std::env::current_dir().unwrap_or_default(),
+            project_root: None,
+            pending: Vec::new(),
+            message: None,
+            command_line: String::new(),
+            options: Options::default(),
+            highlights: Highlights::new(caps.background),
+            keymap: Keymap::default(),
+            typeahead: VecDeque::new(),
+            pending_keys: Vec::new(),
+            script_out: None,
+            quit: None,
+            title: None,
         }
     }
 
     pub fn draw(&mut self) -> Result<()> {
-        self.draw_statusline()?;
-        self.stdout
-            .queue(cursor::MoveTo(self.cursor.x, self.cursor.y))?;
+        match Layout::compute(self.size, self.gutter_width()) {
+            Some(layout) => {
+                self.scroll_to_cursor(&layout);
+                self.draw_gutter(&layout)?;
+                self.draw_text(&layout)?;
+                self.draw_statusline(&layout)?;
+                self.draw_message(&layout)?;
+                self.draw_pending_keys(&layout)?;
+                self.update_title()?;
+                let (x, y) = match self.mode {
+                    Mode::Command => self.command_line_cursor(&layout),
+                    _ => self.screen_cursor(&layout),
+                };
+                self.stdout.queue(cursor::MoveTo(x, y))?;
+            }
+            None => self.draw_too_small()?,
+        }
         self.stdout.flush()?;
         Ok(())
     }
 
-    fn draw_statusline(&mut self) -> Result<()> {
+    /// Room for the largest line number and a space after it, at least four
+    /// columns like vim's `numberwidth`, or none when numbers are off.
+    fn gutter_width(&self) -> u16 {
+        if !self.options.number && !self.options.relative_number {
+            return 0;
+        }
+        let digits = self.buffer.line_count().to_string().len();
+        (digits.max(3) + 1) as u16
+    }
+
+    /// Draws line numbers, right-aligned, or distances from the cursor line
+    /// with `relativenumber`. With both options on, the cursor line shows
+    /// its own number left-aligned like vim does.
+    fn draw_gutter(&mut self, layout: &Layout) -> Result<()> {
+        let area = layout.gutter;
+        if area.width == 0 {
+            return Ok(());
+        }
+        let width = area.width as usize - 1;
+        for row in 0..area.height {
+            let y = self.top + row as usize;
+            let text = if y >= self.buffer.line_count() {
+                String::new()
+            } else if !self.options.relative_number {
+                format!("{:>width$}", y + 1)
+            } else if y == self.cursor.y && self.options.number {
+                format!("{:<width$}", y + 1)
+            } else {
+                format!("{:>width$}", y.abs_diff(self.cursor.y))
+            };
+            let text = format!("{} ", fit(&text, width));
+            let group = if y == self.cursor.y {
+                "CursorLineNr"
+            } else {
+                "LineNr"
+            };
+            self.stdout
+                .queue(cursor::MoveTo(area.x, area.y + row))?
+                .queue(self.caps.print(self.highlights.get(group), &text))?;
+        }
+        Ok(())
+    }
+
+    /// Renders the buffer into the text area. Lines longer than the area is
+    /// wide are cut off.
+    fn draw_text(&mut self, layout: &Layout) -> Result<()> {
+        let area = layout.text;
+        for row in 0..area.height {
+            self.stdout.queue(cursor::MoveTo(area.x, area.y + row))?;
+            let line = self.buffer.line(self.top + row as usize).unwrap_or("");
+            let line = indent::for_display(line, self.options.tabstop);
+            let visible = fit_columns(&line, area.width as usize);
+            self.stdout.queue(style::Print(visible))?;
+            // Clearing from the last column would erase its character.
+            if visible.width() < area.width as usize {
+                self.stdout
+                    .queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+            }
+        }
+        if self.options.indent_guides {
+            self.draw_indent_guides(layout)?;
+        }
+        Ok(())
+    }
+
+    /// Draws a guide at every indent level within each line's leading
+    /// whitespace, coloured by depth, with the guide of the block around the
+    /// cursor picked out.
+    fn draw_indent_guides(&mut self, layout: &Layout) -> Result<()> {
+        let area = layout.text;
+        let (tabstop, shiftwidth) = (self.options.tabstop, self.options.shiftwidth);
+        let scope = indent::scope(&self.buffer, self.cursor.y, tabstop, shiftwidth);
+        let guide = self.options.indent_guide_char.to_string();
+        let end = self
+            .buffer
+            .line_count()
+            .min(self.top + area.height as usize);
+        for y in self.top..end {
+            let depth = indent::guide_indent(&self.buffer, y, tabstop);
+            for column in (0..depth.min(area.width as usize)).step_by(shiftwidth) {
+                let group = match &scope {
+                    Some((c, lines)) if *c == column && lines.contains(&y) => {
+                        "IndentGuideScope".to_string()
+                    }
+                    _ => format!(
+                        "IndentGuide{}",
+                        column / shiftwidth % indent::GUIDE_GROUPS + 1
+                    ),
+                };
+                self.stdout
+                    .queue(cursor::MoveTo(
+                        area.x + column as u16,
+                        area.y + (y - self.top) as u16,
+                    ))?
+                    .queue(self.caps.print(self.highlights.get(&group), &guide))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scrolls just far enough for the cursor line to be in view, so the
+    /// view moves a line at a time as the cursor crosses the top or bottom
+    /// edge.
+    fn scroll_to_cursor(&mut self, layout: &Layout) {
+        let height = layout.text.height.max(1) as usize;
+        if self.cursor.y < self.top {
+            self.top = self.cursor.y;
+        } else if self.cursor.y >= self.top + height {
+            self.top = self.cursor.y + 1 - height;
+        }
+    }
+
+    /// Where the cursor is drawn, kept inside the text area.
+    fn screen_cursor(&self, layout: &Layout) -> (u16, u16) {
+        let area = layout.text;
+        let line = self.buffer.line(self.cursor.y).unwrap_or("");
+        let column = indent::column(line, self.cursor.x, self.options.tabstop);
+        let x = column.min(area.width.saturating_sub(1) as usize) as u16;
+        let y = (self.cursor.y - self.top).min(area.height.saturating_sub(1) as usize) as u16;
+        (area.x + x, area.y + y)
+    }
+
+    /// In insert mode, shows keys held back by a possible mapping such as
+    /// `inoremap jk <Esc>` at the cursor, so that typing `j` gives feedback
+    /// before we know whether it is text. The next redraw replaces it with
+    /// the buffer's contents once the keys are resolved.
+    fn draw_pending_keys(&mut self, layout: &Layout) -> Result<()> {
+        if self.mode != Mode::Insert {
+            return Ok(());
+        }
+        let (x, y) = self.screen_cursor(layout);
+        let area = layout.text;
+        let room = (area.x + area.width).saturating_sub(x) as usize;
+        let preview: String = self
+            .pending_keys
+            .iter()
+            .filter_map(|typed| match typed.key {
+                Key::Code(KeyCode::Char(c), KeyModifiers::NONE) => Some(c),
+                _ => None,
+            })
+            .take(room)
+            .collect();
+        if !preview.is_empty() {
+            self.stdout.queue(cursor::MoveTo(x, y))?.queue(
+                self.caps
+                    .print(self.highlights.get("PendingKeys"), &preview),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Draws the message, or the command line while one is being typed.
+    fn draw_message(&mut self, layout: &Layout) -> Result<()> {
+        let area = layout.message;
+        self.stdout.queue(cursor::MoveTo(area.x, area.y))?;
+        if self.mode == Mode::Command {
+            let prompt = self.command_prompt(layout);
+            self.stdout.queue(style::Print(&prompt))?;
+            if prompt.width() < area.width as usize {
+                self.stdout
+                    .queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+            }
+            return Ok(());
+        }
+        let text = match &self.message {
+            Some(message) => fit_columns(&message.text, area.width as usize),
+            None => "",
+        };
+        if !text.is_empty() {
+            let style = match self.message {
+                Some(Message { error: true, .. }) => self.highlights.get("ErrorMsg"),
+                _ => style::ContentStyle::new(),
+            };
+            self.stdout.queue(self.caps.print(style, text))?;
+        }
+        if text.width() < area.width as usize {
+            self.stdout
+                .queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+        }
+        Ok(())
+    }
+
+    /// `:` and the command line, scrolled so that its end stays visible with
+    /// a cell left for the cursor.
+    fn command_prompt(&self, layout: &Layout) -> String {
+        let room = (layout.message.width as usize).saturating_sub(1);
+        let prompt = format!(":{}", self.command_line);
+        let mut start = 0;
+        while prompt[start..].width() > room {
+            start += prompt[start..].chars().next().map_or(1, char::len_utf8);
+        }
+        prompt[start..].to_string()
+    }
+
+    fn command_line_cursor(&self, layout: &Layout) -> (u16, u16) {
+        let area = layout.message;
+        let x = self.command_prompt(layout).width() as u16;
+        (area.x + x, area.y)
+    }
+
+    fn show_message(&mut self, text: impl Into<String>, error: bool) {
+        let text = indent::escape_controls(&text.into()).into_owned();
+        if error {
+            tracing::warn!("{text}");
+        }
+        self.message = Some(Message { text, error });
+    }
+
+    fn draw_too_small(&mut self) -> Result<()> {
+        let msg = fit("window too small", self.size.0 as usize);
+        self.stdout
+            .queue(terminal::Clear(terminal::ClearType::All))?
+            .queue(cursor::MoveTo(0, 0))?
+            .queue(style::Print(msg))?;
+        Ok(())
+    }
+
+    fn draw_statusline(&mut self, layout: &Layout) -> Result<()> {
+        if !self.options.statusline.is_empty() {
+            return self.draw_custom_statusline(layout);
+        }
+        let area = layout.statusline;
+        let width = area.width as usize;
         let mode_str = format!(" {} ", self.mode);
-        let file = " src/main.rs";
+        let mut cwd = format!(" {}", self.display_cwd());
+        if let Some(file) = self.file_name() {
+            cwd = format!("{cwd}  {file}");
+        }
+        if self.buffer.modified() {
+            cwd = format!("{cwd} {}", modified_flag(true));
+        }
         let pos = format!(" {}:{}", self.cursor.y + 1, self.cursor.x + 1);
-        let file_width = self.size.0 - mode_str.len() as u16 - pos.len() as u16 - 2;
-        self.stdout.queue(cursor::MoveTo(0, self.size.1 - 2))?;
-        self.stdout.queue(style::PrintStyledContent(
-            mode_str.bold().black().on_dark_cyan(),
-        ))?;
-        self.stdout
-            .queue(style::PrintStyledContent("".dark_cyan().on_grey()))?;
-        self.stdout.queue(style::PrintStyledContent(
-            format!("{:<width$}", file, width = file_width as usize)
-                .black()
-                .on_grey(),
-        ))?;
-        self.stdout
-            .queue(style::PrintStyledContent("".dark_cyan().on_grey()))?;
-        self.stdout
-            .queue(style::PrintStyledContent(pos.bold().black().on_dark_cyan()))?;
+        self.stdout.queue(cursor::MoveTo(area.x, area.y))?;
+
+        // The mode and position segments plus both separators have to fit
+        // before the file name gets any room; otherwise only the mode shows.
+        let fixed = mode_str.width() + pos.width() + 2;
+        if width < fixed {
+            let mode_str = pad_columns(&mode_str, width);
+            return self.print_segment("StatusLineMode", &mode_str);
+        }
+        let file_width = width - fixed;
+
+        self.print_segment("StatusLineMode", &mode_str)?;
+        self.print_segment("StatusLineSeparator", "")?;
+        self.print_segment("StatusLine", &pad_columns(&cwd, file_width))?;
+        self.print_segment("StatusLineSeparator", "")?;
+        self.print_segment("StatusLinePosition", &pos)?;
 
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        terminal::enable_raw_mode()?;
+    /// Draws the `statusline` option's template, cutting off the right-aligned
+    /// part and then the end of the rest when it doesn't fit.
+    fn draw_custom_statusline(&mut self, layout: &Layout) -> Result<()> {
+        let area = layout.statusline;
+        let rendered = statusline::render(&self.options.statusline, &|field| {
+            self.statusline_field(field)
+        });
+        let length = |runs: &[(String, String)]| -> usize {
+            runs.iter().map(|(_, text)| text.width()).sum()
+        };
+        let mut room = area.width as usize;
+        let mut right = rendered.right;
+        if length(&rendered.left) + length(&right) > room {
+            right.clear();
+        }
+        self.stdout.queue(cursor::MoveTo(area.x, area.y))?;
+        for (group, text) in &rendered.left {
+            let text = fit_columns(text, room);
+            room -= text.width();
+            self.print_segment(group, text)?;
+        }
+        let fill = room - length(&right);
+        self.print_segment("StatusLine", &" ".repeat(fill))?;
+        for (group, text) in &right {
+            self.print_segment(group, text)?;
+        }
+        Ok(())
+    }
+
+    /// The value of a `%` item in the `statusline` and `titlestring` options.
+    fn statusline_field(&self, field: statusline::Field) -> String {
+        match field {
+            statusline::Field::Mode => self.mode.to_string(),
+            statusline::Field::Directory => self.display_cwd(),
+            statusline::Field::File => self.file_name().unwrap_or_default(),
+            statusline::Field::Modified => modified_flag(self.buffer.modified()).to_string(),
+            statusline::Field::Line => (self.cursor.y + 1).to_string(),
+            statusline::Field::Column => (self.cursor.x + 1).to_string(),
+            statusline::Field::Percent => {
+                ((self.cursor.y + 1) * 100 / self.buffer.line_count()).to_string()
+            }
+        }
+    }
+
+    /// Prints `text` styled with `group` drawn over the statusline's base,
+    /// dimmed while the terminal is unfocused.
+    fn print_segment(&mut self, group: &str, text: &str) -> Result<()> {
+        let mut layers = vec![(priority::BASE, "StatusLine"), (priority::SEGMENT, group)];
+        if !self.focused {
+            layers.push((priority::STATE, "StatusLineUnfocused"));
+        }
+        let style = self.highlights.combine(&layers);
+        self.stdout.queue(self.caps.print(style, text))?;
+        Ok(())
+    }
+
+    /// The working directory as the statusline shows it.
+    fn display_cwd(&self) -> String {
+        let cwd = project::display_cwd(&self.cwd, self.project_root.as_deref());
+        indent::escape_controls(&cwd).into_owned()
+    }
+
+    /// The buffer's file relative to the working directory when it is
+    /// inside it, with control characters made printable.
+    fn file_name(&self) -> Option<String> {
+        let path = self.buffer.path.as_deref()?;
+        let path = path.strip_prefix(&self.cwd).unwrap_or(path);
+        Some(indent::escape_controls(&path.display().to_string()).into_owned())
+    }
+
+    /// Opens `path` in place of the current buffer.
+    pub fn open(&mut self, path: &Path) -> Result<()> {
+        self.buffer = Buffer::open(&self.cwd.join(path))?;
+        self.cursor = Cursor { x: 0, y: 0 };
+        self.top = 0;
+        Ok(())
+    }
+
+    /// Writes the buffer to `path`, or to its own file. Like vim, writing an
+    /// unnamed buffer gives it the name, while writing a named one elsewhere
+    /// only saves a copy. Failures end up in the message area.
+    /// Returns whether the write succeeded.
+    fn save(&mut self, path: Option<PathBuf>) -> bool {
+        let Some(path) = path
+            .map(|path| self.cwd.join(path))
+            .or_else(|| self.buffer.path.clone())
+        else {
+            self.show_message("no file name", true);
+            return false;
+        };
+        match self.buffer.write(&path) {
+            Ok(bytes) => {
+                let lines = self.buffer.line_count();
+                let name = path.strip_prefix(&self.cwd).unwrap_or(&path).display();
+                let name = name.to_string();
+                if self.buffer.path.get_or_insert(path.clone()) == &path {
+                    self.buffer.mark_saved();
+                }
+                self.show_message(format!("\"{name}\" {lines}L, {bytes}B written"), false);
+                true
+            }
+            Err(e) => {
+                self.show_message(format!("{e:#}"), true);
+                false
+            }
+        }
+    }
 
+    fn refresh_project_root(&mut self) {
+        self.project_root = project::find_root(&self.cwd, &self.options.root_markers);
+        tracing::debug!(root = ?self.project_root, "project root");
+    }
+
+    /// Keeps the cursor inside the buffer after a move. In normal mode it
+    /// sits on a character, while insert mode may also put it after the last
+    /// one.
+    fn clamp_cursor(&mut self) {
+        self.cursor.y = self.cursor.y.min(self.buffer.line_count() - 1);
+        let len = self.buffer.line_len(self.cursor.y);
+        let max = match self.mode {
+            Mode::Insert => len,
+            Mode::Normal | Mode::Command => len.saturating_sub(1),
+        };
+        self.cursor.x = self.cursor.x.min(max);
+    }
+
+    /// Runs the editor until it quits, returning the status to exit with.
+    pub fn run(&mut self) -> Result<ExitCode> {
+        // Raw mode swallows the SIGINT that Ctrl-c would normally raise, so
+        // the only ways out are a clean quit, an error or a panic. Restore the
+        // terminal on all three.
+        let caps = self.caps;
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = restore_terminal(caps);
+            hook(info);
+        }));
+
+        terminal::enable_raw_mode()?;
+        if caps.alternate_screen {
+            self.stdout.execute(terminal::EnterAlternateScreen)?;
+        }
         self.stdout
-            .execute(terminal::EnterAlternateScreen)?
-            .execute(terminal::Clear(terminal::ClearType::All))?
-            .execute(self.mode.get_cursor_style())?;
+            .execute(event::EnableFocusChange)?
+            .execute(event::EnableBracketedPaste)?;
+        if caps.title {
+            self.stdout.execute(title::PushTitle)?;
+        }
+        self.stdout
+            .execute(terminal::Clear(terminal::ClearType::All))?;
+        self.set_cursor_style(self.mode)?;
+
+        let result = self.event_loop();
+        restore_terminal(caps)?;
+        result?;
+        Ok(self.quit.unwrap_or(ExitCode::SUCCESS))
+    }
+
+    /// Draws into the main screen instead of the alternate one, for terminals
+    /// where the alternate screen is missing or misbehaves.
+    pub fn disable_alternate_screen(&mut self) {
+        self.caps.alternate_screen = false;
+    }
+
+    /// Switches modes. Leaving insert mode this way ends the undo step just
+    /// like Esc does, so the next insert is undone separately.
+    fn set_mode(&mut self, mode: Mode) -> Result<()> {
+        tracing::trace!(from = %self.mode, to = %mode, "mode change");
+        self.buffer.commit();
+        self.set_cursor_style(mode)?;
+        self.mode = mode;
+        Ok(())
+    }
 
-        loop {
+    fn set_cursor_style(&mut self, mode: Mode) -> Result<()> {
+        if self.caps.cursor_style {
+            self.stdout.queue(self.options.cursor_shape(mode))?;
+        }
+        Ok(())
+    }
+
+    fn event_loop(&mut self) -> Result<()> {
+        // Keys from a script are already waiting.
+        self.process_typeahead()?;
+        // When the pending keys may still grow into a mapping, the time at
+        // which to stop waiting for the rest of it.
+        let mut deadline = self.key_timeout().map(|t| Instant::now() + t);
+        while self.quit.is_none() {
             self.draw()?;
-            if let Some(action) = self.handle_event(read()?)? {
-                match action {
-                    Action::Quit => break,
-                    Action::ChangeMode(m) => {
-                        self.stdout.queue(m.get_cursor_style())?;
-                        self.mode = m;
-                    }
-                    Action::MoveUp => self.cursor.y = self.cursor.y.saturating_sub(1),
-                    Action::MoveDown => self.cursor.y += 1,
-                    Action::MoveLeft => self.cursor.x = self.cursor.x.saturating_sub(1),
-                    Action::MoveRight => self.cursor.x += 1,
-                    Action::AddChar(c) => {
-                        self.stdout
-                            .queue(cursor::MoveTo(self.cursor.x, self.cursor.y))?;
-                        self.stdout.queue(style::Print(c))?;
-                        self.cursor.x += 1;
-                        if self.cursor.x > self.size.0 {
-                            self.cursor.y += 1;
-                            self.cursor.x = 0;
+            if let Some(at) = deadline {
+                let timeout = at.saturating_duration_since(Instant::now());
+                if !event::poll(timeout)? {
+                    self.flush_pending_keys()?;
+                    deadline = self.key_timeout().map(|t| Instant::now() + t);
+                    continue;
+                }
+            }
+            match read()? {
+                event::Event::Resize(width, height) => {
+                    self.size = (width, height);
+                    self.stdout
+                        .queue(terminal::Clear(terminal::ClearType::All))?;
+                }
+                event::Event::FocusLost => self.focused = false,
+                event::Event::FocusGained => self.focused = true,
+                event::Event::Paste(text) => {
+                    self.record_paste(&text)?;
+                    self.paste(&text)?;
+                    deadline = self.key_timeout().map(|t| Instant::now() + t);
+                }
+                // Windows reports the release of every key as well as the
+                // press; elsewhere releases only arrive when asked for.
+                event::Event::Key(e) if e.kind == KeyEventKind::Release => {}
+                event::Event::Key(e) => {
+                    self.message = None;
+                    let key = e.into();
+                    self.record_key(&key)?;
+                    self.feed_key(key)?;
+                    deadline = self.key_timeout().map(|t| Instant::now() + t);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, action: Action) -> Result<()> {
+        tracing::debug!(?action, "dispatch");
+        let count = match action {
+            Action::AppendCount(_) | Action::BeginPrefix(_) | Action::Cancel => 1,
+            _ => self.take_count() as usize,
+        };
+        // Moving the cursor in insert mode starts a new undo step, as in vim.
+        if self.mode == Mode::Insert
+            && matches!(
+                action,
+                Action::MoveUp
+                    | Action::MoveDown
+                    | Action::MoveLeft
+                    | Action::MoveRight
+                    | Action::NextSection
+                    | Action::PrevSection
+            )
+        {
+            self.buffer.commit();
+        }
+        match action {
+            // Refuses to drop unsaved changes like `:q`; `:q!` and
+            // `editor.abort` are the ways out without saving.
+            Action::Quit => match self.check_unsaved(false) {
+                Ok(()) => self.quit = Some(ExitCode::SUCCESS),
+                Err(e) => self.show_message(format!("{e:#}"), true),
+            },
+            Action::Abort => self.quit = Some(ExitCode::FAILURE),
+            Action::Cancel => self.cancel()?,
+            Action::AppendCount(digit) => self.append_count(digit),
+            Action::BeginPrefix(c) => self.pending.push(Pending::Prefix(c)),
+            Action::ChangeMode(m) => self.set_mode(m)?,
+            Action::MoveUp => self.cursor.y = self.cursor.y.saturating_sub(count),
+            Action::MoveDown => self.cursor.y = self.cursor.y.saturating_add(count),
+            Action::MoveLeft => self.cursor.x = self.cursor.x.saturating_sub(count),
+            Action::MoveRight => self.cursor.x = self.cursor.x.saturating_add(count),
+            Action::NextSection | Action::PrevSection => {
+                let forward = matches!(action, Action::NextSection);
+                self.cursor.y = motion::section(&self.buffer, self.cursor.y, count, forward);
+                self.cursor.x = 0;
+            }
+            Action::AddChar(c) => {
+                self.buffer.insert_char((self.cursor.y, self.cursor.x), c);
+                self.cursor.x += 1;
+            }
+            Action::CloseTag => {
+                let (y, x) = (self.cursor.y, self.cursor.x);
+                let before: String = self.buffer.line(y).unwrap_or("").chars().take(x).collect();
+                let closing = self
+                    .buffer
+                    .path
+                    .as_deref()
+                    .and_then(markup::detect)
+                    .and_then(|markup| markup::closing_tag(&before, markup));
+                self.buffer.insert_char((y, x), '>');
+                if let Some(name) = closing {
+                    self.buffer.insert_str((y, x + 1), &format!("</{name}>"));
+                }
+                self.cursor.x += 1;
+            }
+            Action::NewLine => {
+                self.buffer.split_line((self.cursor.y, self.cursor.x));
+                self.cursor.y += 1;
+                self.cursor.x = 0;
+            }
+            Action::Undo | Action::Redo => {
+                for _ in 0..count {
+                    let position = match action {
+                        Action::Undo => self.buffer.undo(),
+                        _ => self.buffer.redo(),
+                    };
+                    match position {
+                        Some((y, x)) => (self.cursor.y, self.cursor.x) = (y, x),
+                        None => {
+                            let what = if matches!(action, Action::Undo) {
+                                "oldest"
+                            } else {
+                                "newest"
+                            };
+                            self.show_message(format!("already at {what} change"), false);
+                            break;
                         }
                     }
-                    Action::NewLine => {
-                        self.cursor.x = 0;
-                        self.cursor.y += 1;
-                    }
+                }
+            }
+            Action::Paste(text) => self.paste(&text)?,
+            Action::Save(path) => {
+                self.save(path);
+            }
+            Action::SubmitCommand(line) => {
+                self.set_mode(Mode::Normal)?;
+                if let Err(e) = self.execute_command(&line) {
+                    self.show_message(format!("{e:#}"), true);
+                }
+            }
+            Action::DeleteBackward => {
+                if let Some((y, x)) = self.buffer.delete_backward((self.cursor.y, self.cursor.x)) {
+                    self.cursor.y = y;
+                    self.cursor.x = x;
                 }
             }
         }
+        self.clamp_cursor();
         Ok(())
     }
 
-    fn handle_event(&mut self, e: event::Event) -> Result<Option<Action>> {
+    /// Inserts pasted text, which is also how some terminals deliver a
+    /// string committed by an input method. It is taken literally rather than
+    /// as keys, so it can't trigger mappings.
+    fn paste(&mut self, text: &str) -> Result<()> {
         match self.mode {
-            Mode::Normal => self.handle_normal_event(e),
-            Mode::Insert => self.handle_insert_event(e),
-        }
-    }
-
-    fn handle_insert_event(&mut self, e: event::Event) -> Result<Option<Action>> {
-        match e {
-            event::Event::Key(e) => match e.code {
-                KeyCode::Esc => Ok(Some(Action::ChangeMode(Mode::Normal))),
-                KeyCode::Char(c) => Ok(Some(Action::AddChar(c))),
-                KeyCode::Up => Ok(Some(Action::MoveUp)),
-                KeyCode::Down => Ok(Some(Action::MoveDown)),
-                KeyCode::Left => Ok(Some(Action::MoveLeft)),
-                KeyCode::Right => Ok(Some(Action::MoveRight)),
-                KeyCode::Enter => Ok(Some(Action::NewLine)),
-                _ => Ok(None),
-            },
-            _ => Ok(None),
+            Mode::Insert => {}
+            Mode::Command => {
+                let line = text.lines().next().unwrap_or("");
+                self.command_line.push_str(line);
+                return Ok(());
+            }
+            Mode::Normal => {
+                tracing::debug!("ignoring paste in normal mode");
+                return Ok(());
+            }
         }
+        self.flush_pending_keys()?;
+        for c in text.replace("\r\n", "\n").chars() {
+            match c {
+                '\n' | '\r' => self.apply(Action::NewLine)?,
+                c => self.apply(Action::AddChar(c))?,
+            }
+        }
+        Ok(())
     }
 
-    fn handle_normal_event(&mut self, e: event::Event) -> Result<Option<Action>> {
-        match e {
-            event::Event::Key(e) => match e.code {
-                KeyCode::Char('q') => Ok(Some(Action::Quit)),
-                KeyCode::Char('i') => Ok(Some(Action::ChangeMode(Mode::Insert))),
-                KeyCode::Up | KeyCode::Char('k') => Ok(Some(Action::MoveUp)),
-                KeyCode::Down | KeyCode::Char('j') => Ok(Some(Action::MoveDown)),
-                KeyCode::Left | KeyCode::Char('h') => Ok(Some(Action::MoveLeft)),
-                KeyCode::Right | KeyCode::Char('l') => Ok(Some(Action::MoveRight)),
-                _ => Ok(None),
-            },
+    fn append_count(&mut self, digit: u16) {
+        match self.pending.last_mut() {
+            Some(Pending::Count(n)) => *n = n.saturating_mul(10).saturating_add(digit),
+            _ => self.pending.push(Pending::Count(digit)),
+        }
+    }
+
+    /// Consumes the count typed before an action, defaulting to 1.
+    fn take_count(&mut self) -> u16 {
+        match self.pending.last() {
+            Some(&Pending::Count(n)) => {
+                self.pending.pop();
+                n.max(1)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Aborts whatever is in progress: the innermost pending layer if there is
+    /// one, otherwise the current mode. Cancelling in normal mode with nothing
+    /// pending is a no-op rather than a way to exit.
+    fn cancel(&mut self) -> Result<()> {
+        if let Some(layer) = self.pending.pop() {
+            tracing::debug!(?layer, "cancelled pending input");
+            return Ok(());
+        }
+        match self.mode {
+            Mode::Insert | Mode::Command => {
+                self.command_line.clear();
+                self.set_mode(Mode::Normal)?;
+            }
+            Mode::Normal => tracing::debug!("nothing to cancel"),
+        }
+        Ok(())
+    }
+
+    fn handle_key(&mut self, e: KeyEvent) -> Result<Option<Action>> {
+        let keys = Key::from(e).to_string();
+        match self.mode {
+            Mode::Normal => self.handle_normal_key(e, &keys),
+            Mode::Insert => self.handle_insert_key(e, &keys),
+            Mode::Command => Ok(self.handle_command_key(e, &keys)),
+        }
+    }
+
+    /// Edits the command line directly; only leaving command mode is an
+    /// action. Backspace on an empty line cancels, as in vim.
+    fn handle_command_key(&mut self, e: KeyEvent, keys: &str) -> Option<Action> {
+        if let Some(action) = bindings::builtin(Mode::Command, keys) {
+            return Some(action);
+        }
+        match e.code {
+            KeyCode::Enter => Some(Action::SubmitCommand(std::mem::take(
+                &mut self.command_line,
+            ))),
+            KeyCode::Backspace if self.command_line.is_empty() => Some(Action::Cancel),
+            KeyCode::Backspace => {
+                self.command_line.pop();
+                None
+            }
+            KeyCode::Char(c)
+                if !e
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                self.command_line.push(c);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_insert_key(&mut self, e: KeyEvent, keys: &str) -> Result<Option<Action>> {
+        if let Some(action) = bindings::builtin(Mode::Insert, keys) {
+            return Ok(Some(action));
+        }
+        match e.code {
+            KeyCode::Char(c)
+                if !e
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                Ok(Some(Action::AddChar(c)))
+            }
             _ => Ok(None),
         }
     }
+
+    fn handle_normal_key(&mut self, e: KeyEvent, keys: &str) -> Result<Option<Action>> {
+        let action = bindings::builtin(Mode::Normal, keys);
+        if let Some(&Pending::Prefix(prefix)) = self.pending.last() {
+            if !matches!(action, Some(Action::Cancel)) {
+                self.pending.pop();
+                return Ok(self.prefixed_key(prefix, keys));
+            }
+        }
+        match e.code {
+            KeyCode::Char(c @ '1'..='9') if e.modifiers.is_empty() => {
+                return Ok(Some(Action::AppendCount(digit(c))));
+            }
+            KeyCode::Char('0')
+                if e.modifiers.is_empty()
+                    && matches!(self.pending.last(), Some(Pending::Count(_))) =>
+            {
+                return Ok(Some(Action::AppendCount(0)));
+            }
+            KeyCode::Char(c) if bindings::is_prefix(Mode::Normal, keys) => {
+                return Ok(Some(Action::BeginPrefix(c)));
+            }
+            _ => {}
+        }
+        Ok(action)
+    }
+
+    /// Completes a two-key command. A key that doesn't complete one aborts
+    /// the whole command, count included.
+    fn prefixed_key(&mut self, prefix: char, keys: &str) -> Option<Action> {
+        let action = bindings::builtin(Mode::Normal, &format!("{prefix}{keys}"));
+        if action.is_none() {
+            self.pending.clear();
+        }
+        action
+    }
+}
+
+fn modified_flag(modified: bool) -> &'static str {
+    if modified {
+        "[+]"
+    } else {
+        ""
+    }
+}
+
+fn digit(c: char) -> u16 {
+    c.to_digit(10).unwrap_or(0) as u16
+}
+
+/// Leaves the alternate screen and raw mode. Also called from the panic hook,
+/// so it writes to a fresh stdout handle rather than the editor's.
+fn restore_terminal(caps: Capabilities) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    stdout
+        .execute(event::DisableBracketedPaste)?
+        .execute(event::DisableFocusChange)?;
+    if caps.cursor_style {
+        stdout.execute(SetCursorStyle::DefaultUserShape)?;
+    }
+    if caps.title {
+        stdout.execute(title::PopTitle)?;
+    }
+    if caps.alternate_screen {
+        stdout.execute(terminal::LeaveAlternateScreen)?;
+    } else {
+        // Without an alternate screen to switch back from, don't leave the
+        // statusline behind under the shell prompt.
+        stdout
+            .execute(terminal::Clear(terminal::ClearType::All))?
+            .execute(cursor::MoveTo(0, 0))?;
+    }
+    terminal::disable_raw_mode()?;
+    Ok(())
+}
+
+/// Falls back to a conventional 80x24 when the size can't be queried, e.g.
+/// when stdout is not a tty.
+fn terminal_size() -> (u16, u16) {
+    terminal::size().unwrap_or_else(|e| {
+        tracing::warn!("failed to query terminal size: {e}");
+        (80, 24)
+    })
 }
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{Action, Editor, Mode, Pending};
+
+impl Action {
+    /// Looks up an action by name, such as `move.down 5` or `insert.char x`,
+    /// so that anything able to produce a string can drive the editor the
+    /// same way keys do. Returns the count given to motions along with it.
+    pub fn parse(spec: &str) -> Result<(Self, Option<u16>)> {
+        // Taken before trimming, which would eat pasted spaces.
+        if let Some(text) = spec.strip_prefix("paste ") {
+            return Ok((Action::Paste(unescape(text)?), None));
+        }
+        let spec = spec.trim();
+        let (name, arg) = match spec.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (spec, ""),
+        };
+        let action = match name {
+            "editor.quit" => Action::Quit,
+            "editor.abort" => Action::Abort,
+            "cancel" => Action::Cancel,
+            "mode.normal" => Action::ChangeMode(Mode::Normal),
+            "mode.insert" => Action::ChangeMode(Mode::Insert),
+            "mode.command" => Action::ChangeMode(Mode::Command),
+            "move.up" => Action::MoveUp,
+            "move.down" => Action::MoveDown,
+            "move.left" => Action::MoveLeft,
+            "move.right" => Action::MoveRight,
+            "move.section.next" => Action::NextSection,
+            "move.section.prev" => Action::PrevSection,
+            "insert.newline" => Action::NewLine,
+            "edit.undo" => Action::Undo,
+            "edit.redo" => Action::Redo,
+            "insert.tag.close" => Action::CloseTag,
+            "delete.backward" => Action::DeleteBackward,
+            "insert.char" => {
+                let mut chars = arg.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => return Ok((Action::AddChar(c), None)),
+                    _ => bail!("insert.char expects a single character, got {arg:?}"),
+                }
+            }
+            "command.run" => return Ok((Action::SubmitCommand(arg.to_string()), None)),
+            "buffer.save" => {
+                let path = (!arg.is_empty()).then(|| arg.into());
+                return Ok((Action::Save(path), None));
+            }
+            "count.append" => {
+                let digit = arg
+                    .parse()
+                    .ok()
+                    .filter(|d| *d < 10)
+                    .ok_or_else(|| anyhow!("count.append expects a digit, got {arg:?}"))?;
+                return Ok((Action::AppendCount(digit), None));
+            }
+            _ => bail!("unknown action: {name}"),
+        };
+        if arg.is_empty() {
+            return Ok((action, None));
+        }
+        if !action.takes_count() {
+            bail!("{name} takes no argument");
+        }
+        let count = arg
+            .parse()
+            .with_context(|| format!("{name} expects a count, got {arg:?}"))?;
+        Ok((action, Some(count)))
+    }
+
+    fn takes_count(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::NextSection
+                | Action::PrevSection
+                | Action::Undo
+                | Action::Redo
+        )
+    }
+}
+
+/// Undoes the escaping of `record_paste`: `\\n`, `\\r`, and a backslash
+/// before any other character stands for that character.
+fn unescape(text: &str) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => bail!("trailing backslash in paste"),
+        }
+    }
+    Ok(out)
+}
+
+impl Editor {
+    /// Runs an action from `Action::parse`. Its count multiplies the one
+    /// typed before it, so `nmap J <Plug>(move.down 5)` moves ten lines on
+    /// `2J`.
+    pub(super) fn run_action(&mut self, action: Action, count: Option<u16>) -> Result<()> {
+        if let Some(count) = count {
+            let typed = self.take_count();
+            self.pending
+                .push(Pending::Count(typed.saturating_mul(count)));
+        }
+        self.apply(action)
+    }
+}
@@ -0,0 +1,69 @@
+use super::{Action, Mode};
+
+/// The keys the editor binds itself, written in mapping notation and paired
+/// with the name of the action they run, as used by `<Plug>(name)`. Typing
+/// text in insert mode, editing the command line and counts are handled
+/// directly rather than listed here.
+pub const BUILTIN: &[(Mode, &str, &str)] = &[
+    (Mode::Normal, "<Esc>", "cancel"),
+    (Mode::Normal, "<C-c>", "cancel"),
+    (Mode::Normal, "q", "editor.quit"),
+    (Mode::Normal, "<C-s>", "buffer.save"),
+    (Mode::Normal, "i", "mode.insert"),
+    (Mode::Normal, ":", "mode.command"),
+    (Mode::Normal, "u", "edit.undo"),
+    (Mode::Normal, "<C-r>", "edit.redo"),
+    (Mode::Normal, "h", "move.left"),
+    (Mode::Normal, "j", "move.down"),
+    (Mode::Normal, "k", "move.up"),
+    (Mode::Normal, "l", "move.right"),
+    (Mode::Normal, "<Left>", "move.left"),
+    (Mode::Normal, "<Down>", "move.down"),
+    (Mode::Normal, "<Up>", "move.up"),
+    (Mode::Normal, "<Right>", "move.right"),
+    (Mode::Normal, "]]", "move.section.next"),
+    (Mode::Normal, "[[", "move.section.prev"),
+    (Mode::Insert, "<Esc>", "cancel"),
+    (Mode::Insert, "<C-c>", "cancel"),
+    (Mode::Insert, "<C-s>", "buffer.save"),
+    (Mode::Insert, ">", "insert.tag.close"),
+    (Mode::Insert, "<CR>", "insert.newline"),
+    (Mode::Insert, "<BS>", "delete.backward"),
+    (Mode::Insert, "<Left>", "move.left"),
+    (Mode::Insert, "<Down>", "move.down"),
+    (Mode::Insert, "<Up>", "move.up"),
+    (Mode::Insert, "<Right>", "move.right"),
+    (Mode::Command, "<Esc>", "cancel"),
+    (Mode::Command, "<C-c>", "cancel"),
+];
+
+/// The action bound to `keys` in `mode`.
+pub fn builtin(mode: Mode, keys: &str) -> Option<Action> {
+    let (_, _, name) = BUILTIN
+        .iter()
+        .find(|(m, lhs, _)| *m == mode && *lhs == keys)?;
+    let (action, _) = Action::parse(name).expect("built-in bindings name known actions");
+    Some(action)
+}
+
+/// Whether `keys` start a longer built-in binding, such as `]` for `]]`.
+pub fn is_prefix(mode: Mode, keys: &str) -> bool {
+    BUILTIN
+        .iter()
+        .any(|(m, lhs, _)| *m == mode && lhs.len() > keys.len() && lhs.starts_with(keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::keymap::{keys_to_string, parse_keys};
+
+    #[test]
+    fn bindings_parse() {
+        for (mode, keys, name) in BUILTIN {
+            assert!(Action::parse(name).is_ok(), "{mode} {keys}: {name}");
+            let parsed = parse_keys(keys).unwrap();
+            assert_eq!(keys_to_string(&parsed), *keys, "{mode} {keys}");
+        }
+    }
+}
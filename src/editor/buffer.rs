@@ -0,0 +1,349 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use super::undo::{Edit, History};
+
+/// The text being edited, kept as lines without their line breaks. Positions
+/// are `(line, column)` with the column counted in chars, so callers never
+/// deal with byte offsets.
+pub struct Buffer {
+    lines: Vec<String>,
+    /// The file the buffer was read from and is saved to.
+    pub path: Option<PathBuf>,
+    /// Whether the file uses `\r\n` line breaks, which are written back.
+    crlf: bool,
+    /// Whether the last line ends with a line break. Kept as read, so that
+    /// saving doesn't add one to a file without it; new and empty files get
+    /// one once they have text.
+    eol: bool,
+    history: History,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self {
+            lines: vec![String::new()],
+            path: None,
+            crlf: false,
+            eol: true,
+            history: History::default(),
+        }
+    }
+}
+
+impl Buffer {
+    /// Reads `path`, or starts an empty buffer for it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("can't open {}", path.display())),
+        };
+        // Like vim's fileformat detection, a file is only CRLF if every line
+        // break is; otherwise a stray `\r` stays part of its line.
+        let breaks = text.matches('\n').count();
+        let crlf = breaks > 0 && text.matches("\r\n").count() == breaks;
+        let eol = text.is_empty() || text.ends_with('\n');
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        let lines = text
+            .split('\n')
+            .map(|line| {
+                if crlf {
+                    line.strip_suffix('\r').unwrap_or(line)
+                } else {
+                    line
+                }
+            })
+            .map(String::from)
+            .collect();
+        Ok(Self {
+            lines,
+            path: Some(path.to_path_buf()),
+            crlf,
+            eol,
+            history: History::default(),
+        })
+    }
+
+    /// Writes the buffer to `path` with the line breaks it was read with. An
+    /// empty buffer is written as an empty file.
+    /// Returns the number of bytes written.
+    pub fn write(&self, path: &Path) -> Result<usize> {
+        let eol = if self.crlf { "\r\n" } else { "\n" };
+        let mut text = self.lines.join(eol);
+        if self.eol && !text.is_empty() {
+            text.push_str(eol);
+        }
+        fs::write(path, &text).with_context(|| format!("can't write {}", path.display()))?;
+        Ok(text.len())
+    }
+
+    /// Always at least 1; an empty buffer has one empty line.
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn line(&self, y: usize) -> Option<&str> {
+        self.lines.get(y).map(String::as_str)
+    }
+
+    /// Length of line `y` in chars, or 0 past the end of the buffer.
+    pub fn line_len(&self, y: usize) -> usize {
+        self.line(y).map_or(0, |line| line.chars().count())
+    }
+
+    pub fn insert_char(&mut self, at: (usize, usize), c: char) {
+        self.insert_str(at, c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Inserts `text`, which may contain line breaks, and returns the
+    /// position just after it.
+    pub fn insert_str(&mut self, at: (usize, usize), text: &str) -> (usize, usize) {
+        self.history.record(Edit::Insert {
+            at,
+            text: text.to_string(),
+        });
+        self.raw_insert(at, text)
+    }
+
+    /// Breaks line `y` in two at column `x`.
+    pub fn split_line(&mut self, at: (usize, usize)) {
+        self.insert_str(at, "\n");
+    }
+
+    /// Deletes the char before `(y, x)`, joining the line with the previous
+    /// one at its start. Returns where the cursor ends up, or `None` at the
+    /// very start of the buffer.
+    pub fn delete_backward(&mut self, (y, x): (usize, usize)) -> Option<(usize, usize)> {
+        let start = match (y, x) {
+            (0, 0) => return None,
+            (y, 0) => (y - 1, self.line_len(y - 1)),
+            (y, x) => (y, x - 1),
+        };
+        let text = self.raw_delete(start, (y, x));
+        self.history.record(Edit::Delete { at: start, text });
+        Some(start)
+    }
+
+    /// Whether there are changes since the file was read or last saved.
+    pub fn modified(&self) -> bool {
+        !self.history.is_saved()
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.history.mark_saved();
+    }
+
+    /// Ends the current undo step, e.g. when leaving insert mode.
+    pub fn commit(&mut self) {
+        self.history.commit();
+    }
+
+    /// Reverts the last undo step and returns where it started.
+    pub fn undo(&mut self) -> Option<(usize, usize)> {
+        let group = self.history.undo()?;
+        for edit in group.iter().rev() {
+            match edit {
+                Edit::Insert { at, text } => {
+                    let end = self.raw_end(*at, text);
+                    self.raw_delete(*at, end);
+                }
+                Edit::Delete { at, text } => {
+                    self.raw_insert(*at, text);
+                }
+            }
+        }
+        group.first().map(Edit::position)
+    }
+
+    /// Makes the last undone step again and returns where it started.
+    pub fn redo(&mut self) -> Option<(usize, usize)> {
+        let group = self.history.redo()?;
+        for edit in &group {
+            match edit {
+                Edit::Insert { at, text } => {
+                    self.raw_insert(*at, text);
+                }
+                Edit::Delete { at, text } => {
+                    let end = self.raw_end(*at, text);
+                    self.raw_delete(*at, end);
+                }
+            }
+        }
+        group.first().map(Edit::position)
+    }
+
+    fn raw_insert(&mut self, (y, x): (usize, usize), text: &str) -> (usize, usize) {
+        let line = &mut self.lines[y];
+        let tail = line.split_off(byte_index(line, x));
+        let mut parts = text.split('\n');
+        self.lines[y].push_str(parts.next().unwrap_or(""));
+        let mut end = y;
+        for part in parts {
+            end += 1;
+            self.lines.insert(end, part.to_string());
+        }
+        let x = self.lines[end].chars().count();
+        self.lines[end].push_str(&tail);
+        (end, x)
+    }
+
+    /// Removes the text from `start` up to `end` and returns it.
+    fn raw_delete(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let last = &self.lines[end.0];
+        let tail = last[byte_index(last, end.1)..].to_string();
+        let following: Vec<String> = self.lines.drain(start.0 + 1..=end.0).collect();
+        let first = &mut self.lines[start.0];
+        let mut removed = first.split_off(byte_index(first, start.1));
+        for line in following {
+            removed.push('\n');
+            removed.push_str(&line);
+        }
+        removed.truncate(removed.len() - tail.len());
+        self.lines[start.0].push_str(&tail);
+        removed
+    }
+
+    /// Where `text` inserted at `at` ends.
+    fn raw_end(&self, (y, x): (usize, usize), text: &str) -> (usize, usize) {
+        match text.rsplit_once('\n') {
+            Some((before, last)) => (y + before.matches('\n').count() + 1, last.chars().count()),
+            None => (y, x + text.chars().count()),
+        }
+    }
+}
+
+/// Byte offset of the `x`th char of `line`, or its length past the end.
+fn byte_index(line: &str, x: usize) -> usize {
+    line.char_indices().nth(x).map_or(line.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(buffer: &Buffer) -> String {
+        (0..buffer.line_count())
+            .filter_map(|y| buffer.line(y))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn buffer(text: &str) -> Buffer {
+        let mut buffer = Buffer::default();
+        buffer.insert_str((0, 0), text);
+        buffer.mark_saved();
+        buffer
+    }
+
+    fn round_trip(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("muelsyse-{}-{name}", std::process::id()));
+        fs::write(&path, text).unwrap();
+        let buffer = Buffer::open(&path).unwrap();
+        buffer.write(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        written
+    }
+
+    #[test]
+    fn write_keeps_line_breaks() {
+        for (name, text) in [
+            ("lf", "a\nb\n"),
+            ("crlf", "a\r\nb\r\n"),
+            ("mixed", "a\nb\r\n"),
+            ("mixed-crlf-first", "a\r\nb\n"),
+            ("no-eol", "a\r\nb"),
+            ("empty", ""),
+        ] {
+            assert_eq!(round_trip(name, text), text, "{name}");
+        }
+    }
+
+    #[test]
+    fn stray_carriage_return_is_content() {
+        let path = std::env::temp_dir().join(format!("muelsyse-{}-cr", std::process::id()));
+        fs::write(&path, "a\nb\r\n").unwrap();
+        let buffer = Buffer::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(buffer.line(1), Some("b\r"));
+    }
+
+    #[test]
+    fn insert_str_returns_end_across_lines() {
+        let mut buffer = buffer("héllo");
+        assert_eq!(buffer.insert_str((0, 2), "ä\nöü\n"), (2, 0));
+        assert_eq!(text(&buffer), "héä\nöü\nllo");
+    }
+
+    #[test]
+    fn undo_and_redo_multibyte_insert() {
+        let mut buffer = buffer("añb");
+        buffer.insert_str((0, 2), "日本\n語");
+        assert_eq!(text(&buffer), "añ日本\n語b");
+        assert_eq!(buffer.undo(), Some((0, 2)));
+        assert_eq!(text(&buffer), "añb");
+        assert_eq!(buffer.redo(), Some((0, 2)));
+        assert_eq!(text(&buffer), "añ日本\n語b");
+    }
+
+    #[test]
+    fn delete_backward_joins_lines() {
+        let mut buffer = buffer("ab\nçd");
+        assert_eq!(buffer.delete_backward((1, 0)), Some((0, 2)));
+        assert_eq!(text(&buffer), "abçd");
+        assert_eq!(buffer.delete_backward((0, 3)), Some((0, 2)));
+        assert_eq!(text(&buffer), "abd");
+        assert_eq!(buffer.delete_backward((0, 0)), None);
+        buffer.undo();
+        assert_eq!(text(&buffer), "ab\nçd");
+        buffer.redo();
+        assert_eq!(text(&buffer), "abd");
+    }
+
+    #[test]
+    fn undo_reverts_a_group_in_reverse() {
+        let mut buffer = buffer("x");
+        buffer.insert_char((0, 1), 'é');
+        buffer.split_line((0, 2));
+        buffer.insert_char((1, 0), 'z');
+        buffer.delete_backward((1, 0));
+        assert_eq!(text(&buffer), "xéz");
+        buffer.commit();
+        buffer.undo();
+        assert_eq!(text(&buffer), "x");
+    }
+
+    #[test]
+    fn modified_follows_undo_past_a_save() {
+        let mut buffer = buffer("a");
+        assert!(!buffer.modified());
+        buffer.insert_char((0, 1), 'b');
+        assert!(buffer.modified());
+        buffer.mark_saved();
+        assert!(!buffer.modified());
+        buffer.undo();
+        assert!(buffer.modified());
+        buffer.redo();
+        assert!(!buffer.modified());
+    }
+
+    #[test]
+    fn saved_state_is_lost_when_history_branches() {
+        let mut buffer = buffer("a");
+        buffer.insert_char((0, 1), 'b');
+        buffer.mark_saved();
+        buffer.undo();
+        buffer.insert_char((0, 1), 'c');
+        buffer.commit();
+        assert!(buffer.modified());
+        buffer.undo();
+        assert!(buffer.modified());
+        assert_eq!(buffer.redo(), Some((0, 1)));
+        assert!(buffer.modified());
+    }
+}
@@ -0,0 +1,323 @@
+use std::fmt;
+
+use crossterm::{
+    style::{Color, ContentStyle, PrintStyledContent},
+    Command,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorSupport {
+    /// `NO_COLOR` is set or the terminal is `dumb`: attributes only.
+    None,
+    /// The 8 classic colors and their bright variants, set with SGR 30-37,
+    /// 40-47, 90-97 and 100-107.
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// Whether the terminal's background is dark or light, so that the default
+/// highlights stay readable on either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+/// What the terminal can do, guessed from the environment since there is no
+/// terminfo database to consult.
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub colors: ColorSupport,
+    /// Whether the terminal understands DECSCUSR (`SetCursorStyle`).
+    pub cursor_style: bool,
+    pub alternate_screen: bool,
+    /// Whether the window title can be set with OSC 2 and saved and restored
+    /// with XTWINOPS.
+    pub title: bool,
+    pub background: Background,
+}
+
+impl Capabilities {
+    pub fn detect() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let colors =
+            if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) || term == "dumb" {
+                ColorSupport::None
+            } else if colorterm == "truecolor" || colorterm == "24bit" {
+                ColorSupport::TrueColor
+            } else if term.contains("256color") || cfg!(windows) {
+                ColorSupport::Ansi256
+            } else {
+                ColorSupport::Ansi16
+            };
+        // The Linux console and hardware terminals ignore DECSCUSR at best
+        // and print it at worst.
+        let cursor_style = !matches!(
+            term.as_str(),
+            "dumb" | "linux" | "cons25" | "ansi" | "vt100" | "vt102" | "vt220"
+        );
+        let background = if term == "dumb" {
+            None
+        } else {
+            query_background()
+        };
+        let caps = Self {
+            colors,
+            cursor_style,
+            alternate_screen: term != "dumb",
+            // The Windows console has no title stack to restore the old
+            // title from, so leave it alone rather than leave ours behind.
+            title: !cfg!(windows) && !matches!(term.as_str(), "dumb" | "linux" | "cons25"),
+            background: background
+                .or_else(background_from_env)
+                .unwrap_or(Background::Dark),
+        };
+        tracing::info!(term, colorterm, ?caps, "detected terminal capabilities");
+        caps
+    }
+
+    /// Prints `text` in `style`, reduced to what the terminal can show.
+    pub fn print<'a>(&self, style: ContentStyle, text: &'a str) -> PrintAdapted<'a> {
+        PrintAdapted {
+            style: ContentStyle {
+                foreground_color: style.foreground_color.and_then(|c| self.adapt(c)),
+                background_color: style.background_color.and_then(|c| self.adapt(c)),
+                underline_color: style.underline_color.and_then(|c| self.adapt(c)),
+                attributes: style.attributes,
+            },
+            text,
+            colors: self.colors,
+        }
+    }
+
+    fn adapt(&self, color: Color) -> Option<Color> {
+        match (self.colors, color) {
+            (ColorSupport::None, _) => None,
+            (ColorSupport::TrueColor, c) => Some(c),
+            (ColorSupport::Ansi256, Color::Rgb { r, g, b }) => {
+                Some(Color::AnsiValue(rgb_to_256(r, g, b)))
+            }
+            (ColorSupport::Ansi16, Color::Rgb { r, g, b }) => Some(nearest_16((r, g, b))),
+            (ColorSupport::Ansi16, Color::AnsiValue(n)) if n >= 16 => {
+                Some(nearest_16(ansi_to_rgb(n)))
+            }
+            (ColorSupport::Ansi16, Color::AnsiValue(n)) => Some(PALETTE[n as usize].0),
+            (_, c) => Some(c),
+        }
+    }
+}
+
+/// Asks the terminal for its background color with OSC 11. The query is
+/// followed by one for the primary device attributes, which every terminal
+/// answers, so that a terminal ignoring OSC 11 doesn't hold up startup until
+/// the timeout.
+#[cfg(unix)]
+fn query_background() -> Option<Background> {
+    use std::{
+        io::{IsTerminal, Write},
+        time::Duration,
+    };
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return None;
+    }
+    crossterm::terminal::enable_raw_mode().ok()?;
+    let reply = (|| {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(b"\x1b]11;?\x1b\\\x1b[c").ok()?;
+        stdout.flush().ok()?;
+        read_reply(Duration::from_millis(300))
+    })();
+    if reply.is_none() {
+        // Drop whatever part of the replies has arrived, so that it isn't
+        // echoed once raw mode is off or read as keys later on.
+        // SAFETY: tcflush only takes a file descriptor and a constant.
+        unsafe { libc::tcflush(libc::STDIN_FILENO, libc::TCIFLUSH) };
+    }
+    let _ = crossterm::terminal::disable_raw_mode();
+    let reply = reply?;
+    tracing::debug!(?reply, "background query reply");
+    parse_background(&reply)
+}
+
+#[cfg(not(unix))]
+fn query_background() -> Option<Background> {
+    None
+}
+
+/// Reads stdin until the device attributes reply (`ESC [ ? ... c`) arrives
+/// or `timeout` passes, without blocking past it.
+#[cfg(unix)]
+fn read_reply(timeout: std::time::Duration) -> Option<String> {
+    use std::time::Instant;
+
+    let deadline = Instant::now() + timeout;
+    let mut reply = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fd` is a single valid pollfd for the duration of the call.
+        if unsafe { libc::poll(&mut fd, 1, remaining.as_millis() as libc::c_int) } <= 0 {
+            return None;
+        }
+        let mut buf = [0u8; 256];
+        // SAFETY: `buf` is valid for writes of its length.
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n <= 0 {
+            return None;
+        }
+        reply.extend_from_slice(&buf[..n as usize]);
+        let text = String::from_utf8_lossy(&reply);
+        if let Some(start) = text.find("\x1b[?") {
+            if text[start..].contains('c') {
+                return Some(text.into_owned());
+            }
+        }
+    }
+}
+
+/// Picks the background out of an OSC 11 reply such as
+/// `ESC ] 11 ; rgb:ffff/ffff/ffff ESC \`, where each channel has one to four
+/// hex digits.
+#[cfg(unix)]
+fn parse_background(reply: &str) -> Option<Background> {
+    let start = reply.find("]11;rgb:")? + "]11;rgb:".len();
+    let rest = &reply[start..];
+    let end = rest.find(['\x1b', '\x07'])?;
+    let mut channels = rest[..end].split('/').map(|hex| {
+        let max = 16f64.powi(hex.len() as i32) - 1.0;
+        u32::from_str_radix(hex, 16).ok().map(|v| v as f64 / max)
+    });
+    let (r, g, b) = (channels.next()??, channels.next()??, channels.next()??);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    Some(if luma < 0.5 {
+        Background::Dark
+    } else {
+        Background::Light
+    })
+}
+
+/// rxvt and Konsole export `COLORFGBG` as `fg;bg` palette indices, where a
+/// background of 7 or 9-15 is light.
+fn background_from_env() -> Option<Background> {
+    let value = std::env::var("COLORFGBG").ok()?;
+    let bg: u8 = value.rsplit(';').next()?.parse().ok()?;
+    Some(match bg {
+        7 | 9..=15 => Background::Light,
+        _ => Background::Dark,
+    })
+}
+
+/// Crossterm always emits named colors as `38;5;n`, which terminals limited
+/// to 16 colors may not understand, so those get the classic SGR codes.
+pub struct PrintAdapted<'a> {
+    style: ContentStyle,
+    text: &'a str,
+    colors: ColorSupport,
+}
+
+impl Command for PrintAdapted<'_> {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        if self.colors != ColorSupport::Ansi16 {
+            return PrintStyledContent(self.style.apply(self.text)).write_ansi(f);
+        }
+        let mut codes = Vec::new();
+        if let Some(i) = self.style.foreground_color.and_then(index_16) {
+            codes.push(if i < 8 { 30 + i } else { 90 + i - 8 });
+        }
+        if let Some(i) = self.style.background_color.and_then(index_16) {
+            codes.push(if i < 8 { 40 + i } else { 100 + i - 8 });
+        }
+        for code in &codes {
+            write!(f, "\x1b[{code}m")?;
+        }
+        let plain = ContentStyle {
+            foreground_color: None,
+            background_color: None,
+            ..self.style
+        };
+        PrintStyledContent(plain.apply(self.text)).write_ansi(f)?;
+        if !codes.is_empty() {
+            f.write_str("\x1b[0m")?;
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> std::io::Result<()> {
+        PrintStyledContent(self.style.apply(self.text)).execute_winapi()
+    }
+}
+
+/// The 16 named colors in SGR order, with xterm's default RGB values.
+const PALETTE: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+fn index_16(color: Color) -> Option<u8> {
+    PALETTE
+        .iter()
+        .position(|&(c, _)| c == color)
+        .map(|i| i as u8)
+}
+
+fn nearest_16((r, g, b): (u8, u8, u8)) -> Color {
+    let distance = |&(_, (pr, pg, pb)): &(Color, (u8, u8, u8))| {
+        let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    PALETTE
+        .iter()
+        .min_by_key(|entry| distance(entry))
+        .map_or(Color::Reset, |&(c, _)| c)
+}
+
+/// Maps onto the 6x6x6 color cube or the grayscale ramp of the 256-color
+/// palette.
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            248..=255 => 231,
+            v => 232 + (v - 8) / 10,
+        };
+    }
+    let level = |v: u8| (v as u16 * 5 / 255) as u8;
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+fn ansi_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => PALETTE[n as usize].1,
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let v = 8 + (n - 232) * 10;
+            (v, v, v)
+        }
+    }
+}
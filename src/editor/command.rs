@@ -0,0 +1,186 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{
+    bindings::BUILTIN,
+    capabilities::Background,
+    highlight::Highlights,
+    keymap::{keys_to_string, parse_keys, Mapping},
+    Action, Editor, Mode,
+};
+
+impl Editor {
+    /// Runs a single ex-style command such as `nnoremap H 0` or
+    /// `set timeoutlen=500`, from the config file or the command line. A `!`
+    /// after the name forces commands that would lose changes.
+    pub(super) fn execute_command(&mut self, line: &str) -> Result<()> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let (name, args) = match line.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (line, ""),
+        };
+        let (name, force) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let path = (!args.is_empty()).then(|| PathBuf::from(args));
+        match name {
+            "q" | "quit" => {
+                self.check_unsaved(force)?;
+                self.quit = Some(ExitCode::SUCCESS);
+                Ok(())
+            }
+            "cq" | "cquit" => self.apply(Action::Abort),
+            "w" | "write" => {
+                self.save(path);
+                Ok(())
+            }
+            "wq" | "x" => {
+                if self.save(path) {
+                    self.quit = Some(ExitCode::SUCCESS);
+                }
+                Ok(())
+            }
+            "e" | "edit" => {
+                self.check_unsaved(force)?;
+                let path = path
+                    .or_else(|| self.buffer.path.clone())
+                    .ok_or_else(|| anyhow!("no file name"))?;
+                self.open(&path)
+            }
+            "map" | "nmap" => self.map_command(Mode::Normal, args, false),
+            "noremap" | "nnoremap" => self.map_command(Mode::Normal, args, true),
+            "imap" => self.map_command(Mode::Insert, args, false),
+            "inoremap" => self.map_command(Mode::Insert, args, true),
+            "cmap" => self.map_command(Mode::Command, args, false),
+            "cnoremap" => self.map_command(Mode::Command, args, true),
+            "highlight" | "hi" => self.highlights.command(args),
+            "colorscheme" | "colo" => {
+                // Like vim, this replaces any `highlight` changes made so far.
+                let background = match args {
+                    "" => self.caps.background,
+                    "dark" => Background::Dark,
+                    "light" => Background::Light,
+                    _ => bail!("unknown colorscheme: {args}"),
+                };
+                self.highlights = Highlights::new(background);
+                Ok(())
+            }
+            "set" | "se" => {
+                for arg in &split_args(args) {
+                    self.options.set(arg)?;
+                    if arg.starts_with("rootmarkers=") {
+                        self.refresh_project_root();
+                    }
+                }
+                Ok(())
+            }
+            "cd" => self.cd_command(args),
+            "keymap" => match args.split_once(char::is_whitespace) {
+                Some(("export", path)) => self.export_keymap(Path::new(path.trim())),
+                _ => bail!("expected keymap export {{file}}"),
+            },
+            _ => bail!("not an editor command: {line}"),
+        }
+    }
+
+    pub(super) fn check_unsaved(&self, force: bool) -> Result<()> {
+        if self.buffer.modified() && !force {
+            bail!("no write since last change (add ! to override)");
+        }
+        Ok(())
+    }
+
+    /// Changes the working directory; with no argument, to the home directory.
+    fn cd_command(&mut self, args: &str) -> Result<()> {
+        let home = dirs::home_dir().ok_or_else(|| anyhow!("no home directory"))?;
+        let dir = match args {
+            "" => home,
+            _ => match args.strip_prefix('~') {
+                Some(rest) => home.join(rest.trim_start_matches(['/', '\\'])),
+                None => PathBuf::from(args),
+            },
+        };
+        std::env::set_current_dir(&dir)
+            .with_context(|| format!("can't change directory to {}", dir.display()))?;
+        self.cwd = std::env::current_dir()?;
+        self.refresh_project_root();
+        Ok(())
+    }
+
+    fn map_command(&mut self, mode: Mode, args: &str, noremap: bool) -> Result<()> {
+        let (lhs, rhs) = args
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("expected {{lhs}} {{rhs}}, got {args:?}"))?;
+        let mapping = Mapping {
+            rhs: parse_keys(rhs.trim())?,
+            noremap,
+        };
+        self.keymap.map(mode, parse_keys(lhs)?, mapping);
+        Ok(())
+    }
+
+    /// Writes the bindings of every mode to `path` as a markdown cheat
+    /// sheet: the user's mappings, then the built-in keys, which mappings of
+    /// the same keys take precedence over.
+    fn export_keymap(&self, path: &Path) -> Result<()> {
+        let cell = |s: &str| format!("`{}`", s.replace('|', "\\|"));
+        let mut out = String::from("# Key mappings\n");
+        for mode in [Mode::Normal, Mode::Insert, Mode::Command] {
+            writeln!(out, "\n## {mode}\n")?;
+            let mappings = self.keymap.mappings(mode);
+            if !mappings.is_empty() {
+                writeln!(out, "### Mappings\n")?;
+                writeln!(out, "| Keys | Runs | Remap |")?;
+                writeln!(out, "| --- | --- | --- |")?;
+                for (lhs, mapping) in mappings {
+                    let rhs = keys_to_string(&mapping.rhs);
+                    let remap = if mapping.noremap { "no" } else { "yes" };
+                    writeln!(out, "| {} | {} | {remap} |", cell(&lhs), cell(&rhs))?;
+                }
+                writeln!(out)?;
+            }
+            writeln!(out, "### Built-in\n")?;
+            writeln!(out, "| Keys | Action |")?;
+            writeln!(out, "| --- | --- |")?;
+            for (_, lhs, name) in BUILTIN.iter().filter(|(m, ..)| *m == mode) {
+                writeln!(out, "| {} | {} |", cell(lhs), cell(name))?;
+            }
+        }
+        std::fs::write(path, out).with_context(|| format!("can't write {}", path.display()))
+    }
+}
+
+/// Splits on whitespace that isn't escaped with a backslash, as in
+/// `set statusline=%M\ %d`. Other backslashes are kept for the option to
+/// interpret.
+fn split_args(args: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = args.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|c| c.is_whitespace()) => {
+                word.extend(chars.next());
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
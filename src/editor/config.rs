@@ -0,0 +1,36 @@
+use std::{fs, io, path::PathBuf};
+
+use super::Editor;
+
+/// `$XDG_CONFIG_HOME/muelsyse/config` or the platform equivalent.
+pub fn path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("muelsyse").join("config"))
+}
+
+impl Editor {
+    /// Runs every line of the config file as a command. Lines starting with
+    /// `"` are comments. A bad line is logged and skipped so that one typo
+    /// doesn't take the rest of the config down with it.
+    pub(super) fn load_config(&mut self) {
+        let Some(path) = path() else {
+            return;
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!("failed to read {}: {e}", path.display());
+                return;
+            }
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('"') {
+                continue;
+            }
+            if let Err(e) = self.execute_command(line) {
+                tracing::warn!("{}:{}: {e:#}", path.display(), i + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use crossterm::style::{Attribute, Color, ContentStyle};
+
+use super::{capabilities::Background, indent::GUIDE_GROUPS};
+
+/// Priorities of the layers that can style the same cell. Layers are merged
+/// from low to high, and a higher layer wins for every color or attribute
+/// it sets, so the result doesn't depend on the order they were drawn in.
+pub mod priority {
+    /// The background of a UI element, such as the whole statusline.
+    pub const BASE: u16 = 0;
+    /// A part of a UI element drawn on top of its base.
+    pub const SEGMENT: u16 = 100;
+    /// Reflects the editor's state over everything else, such as losing
+    /// focus.
+    pub const STATE: u16 = 200;
+}
+
+#[derive(Debug, Clone)]
+enum Definition {
+    Style(ContentStyle),
+    /// `hi link From To`: use whatever `To` resolves to.
+    Link(String),
+}
+
+/// Named highlight groups, as with vim's `:highlight`.
+pub struct Highlights {
+    groups: HashMap<String, Definition>,
+}
+
+impl Highlights {
+    /// The default groups, in the variant readable on `background`.
+    pub fn new(background: Background) -> Self {
+        let mut hl = Self {
+            groups: HashMap::new(),
+        };
+        let (fg, bg, accent, faded) = match background {
+            Background::Dark => (Color::Black, Color::Grey, Color::DarkCyan, Color::DarkGrey),
+            Background::Light => (Color::White, Color::DarkGrey, Color::DarkBlue, Color::Grey),
+        };
+        hl.set("StatusLine", style(Some(fg), Some(bg), &[]));
+        hl.set(
+            "StatusLineMode",
+            style(Some(fg), Some(accent), &[Attribute::Bold]),
+        );
+        hl.set("StatusLineSeparator", style(Some(accent), None, &[]));
+        hl.link("StatusLinePosition", "StatusLineMode");
+        hl.set(
+            "StatusLineUnfocused",
+            style(Some(faded), None, &[Attribute::Dim]),
+        );
+        hl.set("PendingKeys", style(None, None, &[Attribute::Dim]));
+        hl.set("IndentGuide", style(Some(faded), None, &[]));
+        for depth in 1..=GUIDE_GROUPS {
+            hl.link(&format!("IndentGuide{depth}"), "IndentGuide");
+        }
+        hl.set("IndentGuideScope", style(Some(accent), None, &[]));
+        hl.set("LineNr", style(Some(faded), None, &[]));
+        hl.set(
+            "CursorLineNr",
+            style(Some(accent), None, &[Attribute::Bold]),
+        );
+        hl.set(
+            "ErrorMsg",
+            style(Some(Color::White), Some(Color::DarkRed), &[]),
+        );
+        hl
+    }
+
+    pub fn set(&mut self, name: &str, style: ContentStyle) {
+        self.groups
+            .insert(name.to_string(), Definition::Style(style));
+    }
+
+    pub fn link(&mut self, from: &str, to: &str) {
+        self.groups
+            .insert(from.to_string(), Definition::Link(to.to_string()));
+    }
+
+    /// Follows links to the style of `name`. Unknown groups and link cycles
+    /// resolve to the terminal's default style.
+    pub fn get(&self, name: &str) -> ContentStyle {
+        let mut name = name;
+        for _ in 0..self.groups.len() {
+            match self.groups.get(name) {
+                Some(Definition::Style(style)) => return *style,
+                Some(Definition::Link(to)) => name = to,
+                None => break,
+            }
+        }
+        ContentStyle::new()
+    }
+
+    /// Merges `(priority, group)` layers covering the same cell. Equal
+    /// priorities keep their relative order.
+    pub fn combine(&self, layers: &[(u16, &str)]) -> ContentStyle {
+        let mut layers = layers.to_vec();
+        layers.sort_by_key(|&(priority, _)| priority);
+        layers.iter().fold(ContentStyle::new(), |acc, (_, name)| {
+            merge(acc, self.get(name))
+        })
+    }
+
+    /// Runs the arguments of a `highlight` command: either
+    /// `link From To` or `Group fg=color bg=color attr...`.
+    pub fn command(&mut self, args: &str) -> Result<()> {
+        let mut words = args.split_whitespace();
+        match words.next() {
+            Some("link") => match (words.next(), words.next()) {
+                (Some(from), Some(to)) => self.link(from, to),
+                _ => bail!("expected link {{from}} {{to}}"),
+            },
+            Some(name) => {
+                let mut style = ContentStyle::new();
+                for word in words {
+                    match word.split_once('=') {
+                        Some(("fg", color)) => style.foreground_color = Some(parse_color(color)?),
+                        Some(("bg", color)) => style.background_color = Some(parse_color(color)?),
+                        _ => style.attributes.set(parse_attribute(word)?),
+                    }
+                }
+                self.set(name, style);
+            }
+            None => bail!("expected a highlight group"),
+        }
+        Ok(())
+    }
+}
+
+fn style(fg: Option<Color>, bg: Option<Color>, attributes: &[Attribute]) -> ContentStyle {
+    ContentStyle {
+        foreground_color: fg,
+        background_color: bg,
+        underline_color: None,
+        attributes: attributes.into(),
+    }
+}
+
+fn merge(below: ContentStyle, above: ContentStyle) -> ContentStyle {
+    ContentStyle {
+        foreground_color: above.foreground_color.or(below.foreground_color),
+        background_color: above.background_color.or(below.background_color),
+        underline_color: above.underline_color.or(below.underline_color),
+        attributes: below.attributes | above.attributes,
+    }
+}
+
+/// Accepts crossterm color names (`dark_cyan`), `#rrggbb` and 256-color
+/// indices.
+fn parse_color(s: &str) -> Result<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2).unwrap_or(""), 16);
+        if let (6, Ok(r), Ok(g), Ok(b)) = (hex.len(), channel(0), channel(2), channel(4)) {
+            return Ok(Color::Rgb { r, g, b });
+        }
+    }
+    if let Ok(n) = s.parse() {
+        return Ok(Color::AnsiValue(n));
+    }
+    Color::try_from(s).map_err(|_| anyhow!("unknown color: {s}"))
+}
+
+fn parse_attribute(s: &str) -> Result<Attribute> {
+    Ok(match s {
+        "bold" => Attribute::Bold,
+        "dim" => Attribute::Dim,
+        "italic" => Attribute::Italic,
+        "underline" => Attribute::Underlined,
+        "reverse" => Attribute::Reverse,
+        "strikethrough" => Attribute::CrossedOut,
+        _ => bail!("unknown attribute: {s}"),
+    })
+}
@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+
+use unicode_width::UnicodeWidthChar;
+
+use super::buffer::Buffer;
+
+/// How many highlight groups indent guides cycle through by depth, from
+/// `IndentGuide1` up.
+pub const GUIDE_GROUPS: usize = 6;
+
+/// Width of a char at screen column `column`, with tabs reaching the next
+/// multiple of `tabstop` and other control characters shown as by
+/// `control_char`.
+fn char_width(c: char, column: usize, tabstop: usize) -> usize {
+    match c {
+        '\t' => tabstop - column % tabstop,
+        c if c.is_control() => control_char(c).len(),
+        c => c.width().unwrap_or(0),
+    }
+}
+
+/// How a control character is shown, so that text from a file can never
+/// reach the terminal as an escape sequence: `^[` for Esc and the rest of
+/// C0, `^?` for Del, and `<9b>` for C1 like vim.
+fn control_char(c: char) -> String {
+    match c as u32 {
+        n @ 0..=0x1f => format!("^{}", char::from(n as u8 + b'@')),
+        0x7f => "^?".to_string(),
+        n => format!("<{n:02x}>"),
+    }
+}
+
+/// Screen column of char `x` of `line`.
+pub fn column(line: &str, x: usize, tabstop: usize) -> usize {
+    line.chars()
+        .take(x)
+        .fold(0, |column, c| column + char_width(c, column, tabstop))
+}
+
+/// `s` with every control character, tabs included, in its printable form,
+/// for names and messages that have no columns to expand tabs to.
+pub fn escape_controls(s: &str) -> Cow<'_, str> {
+    if !s.contains(char::is_control) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(
+        s.chars()
+            .map(|c| match c {
+                c if c.is_control() => control_char(c),
+                c => c.to_string(),
+            })
+            .collect(),
+    )
+}
+
+/// `line` as drawn: tabs replaced by the spaces they take up on screen and
+/// other control characters by their printable form.
+pub fn for_display(line: &str, tabstop: usize) -> Cow<'_, str> {
+    if !line.contains(char::is_control) {
+        return Cow::Borrowed(line);
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        let width = char_width(c, column, tabstop);
+        match c {
+            '\t' => out.push_str(&" ".repeat(width)),
+            c if c.is_control() => out.push_str(&control_char(c)),
+            c => out.push(c),
+        }
+        column += width;
+    }
+    Cow::Owned(out)
+}
+
+/// Screen width of the leading whitespace of `line`, or `None` if the line
+/// is blank.
+fn indent(line: &str, tabstop: usize) -> Option<usize> {
+    if line.chars().all(char::is_whitespace) {
+        return None;
+    }
+    let x = line.chars().take_while(|c| c.is_whitespace()).count();
+    Some(column(line, x, tabstop))
+}
+
+/// The indent to draw guides for on line `y`. A blank line takes the
+/// smaller indent of the lines around it, so that guides run through the
+/// gaps in a block.
+pub fn guide_indent(buffer: &Buffer, y: usize, tabstop: usize) -> usize {
+    let line_indent = |y: usize| buffer.line(y).and_then(|line| indent(line, tabstop));
+    if let Some(indent) = line_indent(y) {
+        return indent;
+    }
+    let above = (0..y).rev().find_map(line_indent);
+    let below = (y + 1..buffer.line_count()).find_map(line_indent);
+    above.unwrap_or(0).min(below.unwrap_or(0))
+}
+
+/// The guide marking the block around line `y`: its column and the range of
+/// lines it spans. The block is the run of lines indented at least as far
+/// as line `y`, and its guide is the one just left of that indent.
+pub fn scope(
+    buffer: &Buffer,
+    y: usize,
+    tabstop: usize,
+    shiftwidth: usize,
+) -> Option<(usize, std::ops::RangeInclusive<usize>)> {
+    let depth = guide_indent(buffer, y, tabstop);
+    if depth < shiftwidth {
+        return None;
+    }
+    let column = (depth - 1) / shiftwidth * shiftwidth;
+    let inside = |y: &usize| guide_indent(buffer, *y, tabstop) > column;
+    let first = (0..y).rev().take_while(inside).last().unwrap_or(y);
+    let last = (y + 1..buffer.line_count())
+        .take_while(inside)
+        .last()
+        .unwrap_or(y);
+    Some((column, first..=last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indent_counts_screen_columns() {
+        assert_eq!(indent("    a", 8), Some(4));
+        assert_eq!(indent("\t a", 4), Some(5));
+        assert_eq!(indent("\u{3000}ab", 8), Some(2));
+        assert_eq!(indent(" \t ", 8), None);
+    }
+
+    #[test]
+    fn column_expands_tabs_and_control_chars() {
+        assert_eq!(column("a\tb", 2, 4), 4);
+        assert_eq!(column("\x1b[m", 1, 8), 2);
+        assert_eq!(column("日本", 1, 8), 2);
+        assert_eq!(for_display("a\tb\x1b", 4), "a   b^[");
+        assert_eq!(escape_controls("a\tb\x1b]0;x\x07"), "a^Ib^[]0;x^G");
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::{
+    keymap::{Key, Lookup, Mapping, Typed, MAX_MAP_DEPTH},
+    Action, Editor, Mode,
+};
+
+impl Editor {
+    pub(super) fn feed_key(&mut self, key: Key) -> Result<()> {
+        self.typeahead.push_back(Typed::new(key));
+        self.process_typeahead()
+    }
+
+    /// How long to wait for the pending keys to complete a mapping, or `None`
+    /// if nothing is pending.
+    pub(super) fn key_timeout(&self) -> Option<Duration> {
+        let first = &self.pending_keys.first()?.key;
+        let ms = match first {
+            Key::Code(KeyCode::Esc, _) => self.options.ttimeoutlen,
+            _ => self.options.timeoutlen,
+        };
+        Some(Duration::from_millis(ms))
+    }
+
+    /// Gives up waiting for a longer mapping: applies the longest mapping the
+    /// pending keys complete, or passes them through unmapped.
+    pub(super) fn flush_pending_keys(&mut self) -> Result<()> {
+        if !self.pending_keys.is_empty() {
+            self.resolve_pending(true)?;
+            self.process_typeahead()?;
+        }
+        Ok(())
+    }
+
+    /// Runs keys through the keymap until the typeahead is empty or the keys
+    /// seen so far could still grow into a longer mapping.
+    pub(super) fn process_typeahead(&mut self) -> Result<()> {
+        while let Some(mut typed) = self.typeahead.pop_front() {
+            if self.quit.is_some() {
+                self.typeahead.clear();
+                break;
+            }
+            // Only typed keys go through langmap; mappings are written in
+            // the layout the commands expect.
+            if typed.depth == 0 && !typed.translated && self.mode == Mode::Normal {
+                typed.translated = true;
+                if let Key::Code(KeyCode::Char(c), KeyModifiers::NONE) = typed.key {
+                    if let Some(&to) = self.options.langmap.get(&c) {
+                        typed.key = Key::Code(KeyCode::Char(to), KeyModifiers::NONE);
+                    }
+                }
+            }
+            if !typed.remap {
+                if self.pending_keys.is_empty() {
+                    self.dispatch_key(typed.key)?;
+                } else {
+                    // Keys from a noremap mapping can't complete a mapping
+                    // either, so settle what was pending before them first.
+                    self.typeahead.push_front(typed);
+                    self.resolve_pending(true)?;
+                }
+                continue;
+            }
+            self.pending_keys.push(typed);
+            self.resolve_pending(false)?;
+        }
+        Ok(())
+    }
+
+    /// Matches the pending keys against the keymap. Unless `flush` is set,
+    /// keys that are the prefix of a longer mapping are left pending.
+    fn resolve_pending(&mut self, flush: bool) -> Result<()> {
+        let keys: Vec<Key> = self.pending_keys.iter().map(|t| t.key.clone()).collect();
+        match self.keymap.lookup(self.mode, &keys) {
+            Lookup::Prefix
+            | Lookup::Exact {
+                ambiguous: true, ..
+            } if !flush => {}
+            Lookup::Exact { mapping, .. } => {
+                let mapping = mapping.clone();
+                self.expand(&mapping, keys.len());
+            }
+            Lookup::None | Lookup::Prefix => {
+                // The keys can't complete a longer mapping, but a shorter one
+                // may have matched on the way.
+                for len in (1..keys.len()).rev() {
+                    if let Lookup::Exact { mapping, .. } =
+                        self.keymap.lookup(self.mode, &keys[..len])
+                    {
+                        let mapping = mapping.clone();
+                        self.expand(&mapping, len);
+                        return Ok(());
+                    }
+                }
+                let first = self.pending_keys.remove(0);
+                self.requeue_pending();
+                self.dispatch_key(first.key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the first `len` pending keys with the mapping's right-hand
+    /// side and puts the rest back into the typeahead to be matched again.
+    fn expand(&mut self, mapping: &Mapping, len: usize) {
+        let lhs: Vec<Typed> = self.pending_keys.drain(..len).collect();
+        let depth = lhs.iter().map(|t| t.depth).max().unwrap_or(0) + 1;
+        if depth > MAX_MAP_DEPTH {
+            let lhs: String = lhs.iter().map(|t| t.key.to_string()).collect();
+            self.show_message(format!("recursive mapping: {lhs}"), true);
+            self.pending_keys.clear();
+            self.typeahead.clear();
+            return;
+        }
+        self.requeue_pending();
+        for (i, key) in mapping.rhs.iter().enumerate().rev() {
+            // Like vim, `nmap j jzz` doesn't map the leading `j` again.
+            let remap = !(mapping.noremap || (i == 0 && lhs[0].key == *key));
+            self.typeahead.push_front(Typed {
+                key: key.clone(),
+                remap,
+                depth,
+                translated: true,
+            });
+        }
+    }
+
+    fn requeue_pending(&mut self) {
+        for typed in self.pending_keys.drain(..).rev() {
+            self.typeahead.push_front(typed);
+        }
+    }
+
+    fn dispatch_key(&mut self, key: Key) -> Result<()> {
+        match key {
+            // A `<Plug>` key nothing else maps runs the action of that name.
+            Key::Plug(name) => match Action::parse(&name) {
+                Ok((action, count)) => self.run_action(action, count)?,
+                Err(e) => self.show_message(format!("<Plug>({name}): {e:#}"), true),
+            },
+            Key::Code(code, modifiers) => {
+                if let Some(action) = self.handle_key(KeyEvent::new(code, modifiers))? {
+                    self.apply(action)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::capabilities::{Background, Capabilities, ColorSupport};
+
+    /// An editor in insert mode, so that the keys that reach the buffer show
+    /// what the mappings turned the typed keys into.
+    fn editor(maps: &[&str]) -> Editor {
+        let mut editor = Editor::with_caps(Capabilities {
+            colors: ColorSupport::None,
+            cursor_style: false,
+            alternate_screen: false,
+            title: false,
+            background: Background::Dark,
+        });
+        for map in maps {
+            editor.execute_command(map).unwrap();
+        }
+        editor.mode = Mode::Insert;
+        editor
+    }
+
+    fn type_keys(editor: &mut Editor, keys: &str) {
+        for c in keys.chars() {
+            let key = Key::Code(KeyCode::Char(c), KeyModifiers::NONE);
+            editor.feed_key(key).unwrap();
+        }
+    }
+
+    fn text(editor: &Editor) -> &str {
+        editor.buffer.line(0).unwrap()
+    }
+
+    #[test]
+    fn remaps_unless_noremap() {
+        let mut editor = editor(&["imap a b", "imap y a", "inoremap x a"]);
+        type_keys(&mut editor, "yx");
+        assert_eq!(text(&editor), "ba");
+    }
+
+    #[test]
+    fn leading_lhs_key_is_not_remapped() {
+        let mut editor = editor(&["imap j jz"]);
+        type_keys(&mut editor, "j");
+        assert_eq!(text(&editor), "jz");
+    }
+
+    #[test]
+    fn ambiguous_prefix_waits_then_flushes() {
+        let mut editor = editor(&["imap j J", "imap jk K"]);
+        type_keys(&mut editor, "j");
+        assert_eq!(text(&editor), "");
+        assert!(editor.key_timeout().is_some());
+        editor.flush_pending_keys().unwrap();
+        assert_eq!(text(&editor), "J");
+        type_keys(&mut editor, "jk");
+        assert_eq!(text(&editor), "JK");
+        assert!(editor.key_timeout().is_none());
+    }
+
+    #[test]
+    fn falls_back_to_shorter_match() {
+        let mut editor = editor(&["imap ab X", "imap abcd Y"]);
+        type_keys(&mut editor, "abce");
+        assert_eq!(text(&editor), "Xce");
+    }
+
+    #[test]
+    fn unmatched_prefix_passes_through() {
+        let mut editor = editor(&["imap abc X"]);
+        type_keys(&mut editor, "abd");
+        assert_eq!(text(&editor), "abd");
+    }
+
+    #[test]
+    fn recursive_mapping_is_reported() {
+        let mut editor = editor(&["imap a b", "imap b a"]);
+        type_keys(&mut editor, "a");
+        assert_eq!(text(&editor), "");
+        let message = editor.message.as_ref().unwrap();
+        assert!(message.error);
+        assert_eq!(message.text, "recursive mapping: a");
+        assert!(editor.typeahead.is_empty() && editor.pending_keys.is_empty());
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::Mode;
+
+/// How many times a key may be produced by a mapping that was itself
+/// produced by a mapping before we assume the maps are recursive.
+pub const MAX_MAP_DEPTH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Code(KeyCode, KeyModifiers),
+    /// `<Plug>(name)`: can't be typed, only produced by other mappings, so
+    /// plugins can expose actions for users to bind without taking a key.
+    Plug(String),
+}
+
+impl From<KeyEvent> for Key {
+    fn from(e: KeyEvent) -> Self {
+        let mut modifiers = e.modifiers;
+        if let KeyCode::Char(c) = e.code {
+            // Shift is already reflected in the character itself.
+            modifiers -= KeyModifiers::SHIFT;
+            // Windows reports AltGr as Ctrl+Alt on the character it produces,
+            // e.g. `@` on a German layout. Keep Ctrl+Alt+letter chords intact.
+            let altgr = KeyModifiers::CONTROL | KeyModifiers::ALT;
+            if cfg!(windows) && modifiers.contains(altgr) && !c.is_ascii_alphanumeric() {
+                modifiers -= altgr;
+            }
+        }
+        Key::Code(e.code, modifiers)
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (code, modifiers) = match self {
+            Key::Plug(name) => return write!(f, "<Plug>({name})"),
+            Key::Code(code, modifiers) => (code, modifiers),
+        };
+        let name = match code {
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::Enter => "CR".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            // Crossterm reports Shift-Tab as BackTab with the shift modifier.
+            KeyCode::BackTab if modifiers.contains(KeyModifiers::SHIFT) => "Tab".to_string(),
+            KeyCode::BackTab => "S-Tab".to_string(),
+            KeyCode::Backspace => "BS".to_string(),
+            KeyCode::Delete => "Del".to_string(),
+            KeyCode::Up => "Up".to_string(),
+            KeyCode::Down => "Down".to_string(),
+            KeyCode::Left => "Left".to_string(),
+            KeyCode::Right => "Right".to_string(),
+            KeyCode::Home => "Home".to_string(),
+            KeyCode::End => "End".to_string(),
+            KeyCode::PageUp => "PageUp".to_string(),
+            KeyCode::PageDown => "PageDown".to_string(),
+            KeyCode::Insert => "Insert".to_string(),
+            KeyCode::F(n) => format!("F{n}"),
+            other => format!("{other:?}"),
+        };
+        let mut prefix = String::new();
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            prefix.push_str("C-");
+        }
+        if modifiers.contains(KeyModifiers::ALT) {
+            prefix.push_str("A-");
+        }
+        if modifiers.contains(KeyModifiers::SHIFT) {
+            prefix.push_str("S-");
+        }
+        if prefix.is_empty() && name.chars().count() == 1 {
+            f.write_str(&name)
+        } else {
+            write!(f, "<{prefix}{name}>")
+        }
+    }
+}
+
+/// Parses vim-style key notation such as `jk`, `<Esc>`, `<C-w>h` or
+/// `<Plug>(name)`.
+pub fn parse_keys(s: &str) -> Result<Vec<Key>> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if name.eq_ignore_ascii_case("plug") {
+                    let tail = &rest[end + 1..];
+                    let close = tail
+                        .strip_prefix('(')
+                        .and_then(plug_end)
+                        .ok_or_else(|| anyhow!("expected <Plug>(name) in {s:?}"))?;
+                    keys.push(Key::Plug(tail[1..close + 1].to_string()));
+                    rest = &tail[close + 2..];
+                    continue;
+                }
+                if let Some(key) = parse_special(name) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::Code(KeyCode::Char(c), KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        bail!("empty key sequence");
+    }
+    Ok(keys)
+}
+
+/// The index of the `)` ending a `<Plug>` name, skipping ones escaped with a
+/// backslash.
+fn plug_end(name: &str) -> Option<usize> {
+    let mut chars = name.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            ')' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses the inside of a `<...>` key name, or returns `None` so that the
+/// text is taken literally, as vim does for unknown names.
+fn parse_special(name: &str) -> Option<Key> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut base = name;
+    while base.len() > 2 && base.as_bytes()[1] == b'-' {
+        modifiers |= match base.as_bytes()[0].to_ascii_lowercase() {
+            b'c' => KeyModifiers::CONTROL,
+            b'a' | b'm' => KeyModifiers::ALT,
+            b's' => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        base = &base[2..];
+    }
+    let code = match base.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "insert" => KeyCode::Insert,
+        f if f.len() > 1 && f.starts_with('f') => KeyCode::F(f[1..].parse().ok()?),
+        _ if base.chars().count() == 1 && modifiers != KeyModifiers::NONE => {
+            let c = base.chars().next()?;
+            // <C-A> and <C-a> are the same key; a terminal can't tell them apart.
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                KeyCode::Char(c.to_ascii_lowercase())
+            } else {
+                KeyCode::Char(c)
+            }
+        }
+        _ => return None,
+    };
+    if let KeyCode::Char(_) = code {
+        modifiers -= KeyModifiers::SHIFT;
+    }
+    Some(Key::Code(code, modifiers))
+}
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub rhs: Vec<Key>,
+    /// Keys produced by a `noremap` mapping are not mapped again.
+    pub noremap: bool,
+}
+
+pub enum Lookup<'a> {
+    /// No mapping starts with these keys.
+    None,
+    /// The keys are a strict prefix of at least one mapping.
+    Prefix,
+    /// The keys match a mapping; `ambiguous` if a longer one also starts
+    /// with them.
+    Exact {
+        mapping: &'a Mapping,
+        ambiguous: bool,
+    },
+}
+
+#[derive(Default)]
+pub struct Keymap {
+    maps: HashMap<Mode, HashMap<Vec<Key>, Mapping>>,
+}
+
+impl Keymap {
+    pub fn map(&mut self, mode: Mode, lhs: Vec<Key>, mapping: Mapping) {
+        self.maps.entry(mode).or_default().insert(lhs, mapping);
+    }
+
+    pub fn lookup(&self, mode: Mode, keys: &[Key]) -> Lookup<'_> {
+        let Some(maps) = self.maps.get(&mode) else {
+            return Lookup::None;
+        };
+        let longer = maps
+            .keys()
+            .any(|lhs| lhs.len() > keys.len() && lhs.starts_with(keys));
+        match maps.get(keys) {
+            Some(mapping) => Lookup::Exact {
+                mapping,
+                ambiguous: longer,
+            },
+            None if longer => Lookup::Prefix,
+            None => Lookup::None,
+        }
+    }
+
+    /// The mappings of `mode` ordered by their keys, for listing.
+    pub fn mappings(&self, mode: Mode) -> Vec<(String, &Mapping)> {
+        let mut mappings: Vec<_> = self
+            .maps
+            .get(&mode)
+            .into_iter()
+            .flatten()
+            .map(|(lhs, mapping)| (keys_to_string(lhs), mapping))
+            .collect();
+        mappings.sort_by(|a, b| a.0.cmp(&b.0));
+        mappings
+    }
+}
+
+pub fn keys_to_string(keys: &[Key]) -> String {
+    keys.iter().map(Key::to_string).collect()
+}
+
+/// A key waiting to be processed, along with how it was produced.
+#[derive(Debug, Clone)]
+pub struct Typed {
+    pub key: Key,
+    /// False for keys produced by a `noremap` mapping.
+    pub remap: bool,
+    /// Number of mappings that were expanded to produce this key.
+    pub depth: usize,
+    /// Set once langmap has been applied, so that keys put back into the
+    /// typeahead while matching a mapping aren't translated twice.
+    pub translated: bool,
+}
+
+impl Typed {
+    pub fn new(key: Key) -> Self {
+        Self {
+            key,
+            remap: true,
+            depth: 0,
+            translated: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn char_key(c: char) -> Key {
+        Key::Code(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn parses_plain_and_special_keys() {
+        let keys = parse_keys("j<Esc><C-W>h<lt><Space>").unwrap();
+        assert_eq!(
+            keys,
+            [
+                char_key('j'),
+                Key::Code(KeyCode::Esc, KeyModifiers::NONE),
+                Key::Code(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                char_key('h'),
+                char_key('<'),
+                char_key(' '),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_shift_tab_and_function_keys() {
+        assert_eq!(
+            parse_keys("<S-Tab><F12>").unwrap(),
+            [
+                Key::Code(KeyCode::BackTab, KeyModifiers::SHIFT),
+                Key::Code(KeyCode::F(12), KeyModifiers::NONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_plug() {
+        assert_eq!(
+            parse_keys("<Plug>(move.down 5)x").unwrap(),
+            [Key::Plug("move.down 5".to_string()), char_key('x')]
+        );
+        assert!(parse_keys("<Plug>move").is_err());
+        assert_eq!(
+            parse_keys(r"<Plug>(paste a\)b)x").unwrap(),
+            [Key::Plug(r"paste a\)b".to_string()), char_key('x')]
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_literal() {
+        assert_eq!(parse_keys("<foo>").unwrap().len(), 5);
+        assert_eq!(parse_keys("<").unwrap(), [char_key('<')]);
+        assert!(parse_keys("").is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let text = "a<Esc><C-w><lt><Space><CR><Plug>(edit.undo)";
+        assert_eq!(keys_to_string(&parse_keys(text).unwrap()), text);
+    }
+}
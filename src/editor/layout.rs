@@ -0,0 +1,88 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A rectangular region of the terminal, in cells.
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Screen regions derived from the terminal size. All arithmetic here is
+/// saturating so that no terminal size can make rendering panic.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// Line numbers, left of the text; zero wide when they are off.
+    pub gutter: Rect,
+    pub text: Rect,
+    pub statusline: Rect,
+    pub message: Rect,
+}
+
+impl Layout {
+    pub const MIN_WIDTH: u16 = 12;
+    /// Text area, statusline and message line each need a row.
+    pub const MIN_HEIGHT: u16 = 3;
+
+    /// Returns `None` when the terminal is too small to draw the editor. The
+    /// gutter is narrowed if needed to leave the text half the width.
+    pub fn compute((width, height): (u16, u16), gutter_width: u16) -> Option<Self> {
+        if width < Self::MIN_WIDTH || height < Self::MIN_HEIGHT {
+            return None;
+        }
+        let text_height = height.saturating_sub(2);
+        let gutter_width = gutter_width.min(width / 2);
+        Some(Self {
+            gutter: Rect {
+                x: 0,
+                y: 0,
+                width: gutter_width,
+                height: text_height,
+            },
+            text: Rect {
+                x: gutter_width,
+                y: 0,
+                width: width.saturating_sub(gutter_width),
+                height: text_height,
+            },
+            statusline: Rect {
+                x: 0,
+                y: text_height,
+                width,
+                height: 1,
+            },
+            message: Rect {
+                x: 0,
+                y: text_height + 1,
+                width,
+                height: 1,
+            },
+        })
+    }
+}
+
+/// Truncates `s` to at most `width` characters.
+pub fn fit(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+/// Cuts `s` down to at most `width` terminal columns, counting wide
+/// characters as two.
+pub fn fit_columns(s: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in s.char_indices() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+/// Cuts `s` down to `width` terminal columns and pads it with spaces to
+/// fill them.
+pub fn pad_columns(s: &str, width: usize) -> String {
+    let s = fit_columns(s, width);
+    format!("{s}{}", " ".repeat(width - s.width()))
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+/// Elements HTML never closes.
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Markup {
+    Html,
+    /// XML and component languages such as JSX, where every tag is closed.
+    Xml,
+}
+
+/// The markup flavour of a file, guessed from its extension.
+pub fn detect(path: &Path) -> Option<Markup> {
+    match path.extension()?.to_str()? {
+        "html" | "htm" | "vue" | "svelte" => Some(Markup::Html),
+        "xml" | "xhtml" | "svg" | "jsx" | "tsx" => Some(Markup::Xml),
+        _ => None,
+    }
+}
+
+/// The name of the tag that typing `>` after `before` opens, if it should be
+/// closed automatically. Closing tags, self-closing tags, comments,
+/// declarations and HTML's void elements are left alone, and so are type
+/// arguments such as `useState<string>` in JSX and in scripts inside
+/// components.
+pub fn closing_tag(before: &str, markup: Markup) -> Option<&str> {
+    let open = before.rfind('<')?;
+    if !opens_tag(&before[..open]) {
+        return None;
+    }
+    let tag = &before[open + 1..];
+    if tag.contains('>') || tag.ends_with('/') {
+        return None;
+    }
+    let end = tag
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+        .unwrap_or(tag.len());
+    let name = &tag[..end];
+    if name.is_empty() || !name.starts_with(char::is_alphabetic) {
+        return None;
+    }
+    // Type parameters of a generic arrow function, `<T,>` or
+    // `<T extends X>`, rather than a tag's attributes.
+    let attributes = tag[end..].trim_start();
+    if attributes.starts_with(',') || attributes.split_whitespace().next() == Some("extends") {
+        return None;
+    }
+    if markup == Markup::Html && VOID_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+        return None;
+    }
+    Some(name)
+}
+
+/// Whether a `<` after `lead` can start a tag: at the start of the line,
+/// after whitespace, one of `(={>`, or `return`. After an identifier it is a
+/// generic instead.
+fn opens_tag(lead: &str) -> bool {
+    let Some(last) = lead.chars().next_back() else {
+        return true;
+    };
+    if last.is_whitespace() || matches!(last, '(' | '=' | '{' | '>') {
+        return true;
+    }
+    lead.strip_suffix("return").is_some_and(|rest| {
+        !rest
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '$'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closes_tags() {
+        assert_eq!(closing_tag("<div", Markup::Html), Some("div"));
+        assert_eq!(closing_tag("  <a href=\"x\"", Markup::Html), Some("a"));
+        assert_eq!(closing_tag("return (<my-el", Markup::Html), Some("my-el"));
+        assert_eq!(closing_tag("x = <Foo.Bar", Markup::Xml), Some("Foo.Bar"));
+        assert_eq!(closing_tag("<p>text <b", Markup::Html), Some("b"));
+    }
+
+    #[test]
+    fn skips_generics() {
+        assert_eq!(closing_tag("useState<string", Markup::Xml), None);
+        assert_eq!(closing_tag("Array<number", Markup::Xml), None);
+        assert_eq!(closing_tag("const f = <T,", Markup::Xml), None);
+        assert_eq!(closing_tag("const f = <T extends X", Markup::Xml), None);
+        assert_eq!(closing_tag("const f = <T, U", Markup::Xml), None);
+    }
+
+    #[test]
+    fn skips_void_elements_in_html_only() {
+        assert_eq!(closing_tag("<br", Markup::Html), None);
+        assert_eq!(closing_tag("<IMG src=x", Markup::Html), None);
+        assert_eq!(closing_tag("<br", Markup::Xml), Some("br"));
+    }
+
+    #[test]
+    fn skips_closing_self_closing_and_comments() {
+        assert_eq!(closing_tag("</div", Markup::Html), None);
+        assert_eq!(closing_tag("<img /", Markup::Xml), None);
+        assert_eq!(closing_tag("<!-- note --", Markup::Html), None);
+        assert_eq!(closing_tag("<!DOCTYPE html", Markup::Html), None);
+        assert_eq!(closing_tag("<?xml version=\"1.0\"?", Markup::Xml), None);
+        assert_eq!(closing_tag("<div></div", Markup::Html), None);
+        assert_eq!(closing_tag("a < b", Markup::Xml), None);
+    }
+}
@@ -0,0 +1,34 @@
+use super::buffer::Buffer;
+
+/// Line of the `count`th section start after line `y`, or before it when
+/// not `forward`, stopping at the first or last line as vim does.
+///
+/// Without a syntax tree, a section is a run of unindented lines, such as a
+/// doc comment, attributes and the signature of a top-level item. A line
+/// closing a bracket doesn't start one, so `}` ending the previous item is
+/// skipped.
+pub fn section(buffer: &Buffer, y: usize, count: usize, forward: bool) -> usize {
+    let last = buffer.line_count() - 1;
+    let mut y = y;
+    for _ in 0..count {
+        let next = if forward {
+            (y + 1..=last).find(|&y| starts_section(buffer, y))
+        } else {
+            (0..y).rev().find(|&y| starts_section(buffer, y))
+        };
+        match next {
+            Some(next) => y = next,
+            None => return if forward { last } else { 0 },
+        }
+    }
+    y
+}
+
+fn starts_section(buffer: &Buffer, y: usize) -> bool {
+    let top_level = |y: usize| {
+        buffer.line(y).is_some_and(|line| {
+            line.starts_with(|c: char| !c.is_whitespace() && !matches!(c, '}' | ')' | ']'))
+        })
+    };
+    top_level(y) && (y == 0 || !top_level(y - 1))
+}
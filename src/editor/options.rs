@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossterm::cursor::SetCursorStyle;
+
+use super::{statusline, Mode};
+
+/// User-settable options, changed with `set name=value` in the config.
+pub struct Options {
+    /// Milliseconds to wait for a mapped key sequence to complete.
+    pub timeoutlen: u64,
+    /// Milliseconds to wait when the pending keys start with Esc, so that a
+    /// lone Esc isn't held up by mappings such as `<Esc>j`.
+    pub ttimeoutlen: u64,
+    /// Cursor shape per mode, set like `cursorshape=n:block,i:bar-blink`.
+    pub cursor_normal: SetCursorStyle,
+    pub cursor_insert: SetCursorStyle,
+    pub cursor_command: SetCursorStyle,
+    /// Files or directories marking a project root, searched for upwards
+    /// from the working directory.
+    pub root_markers: Vec<String>,
+    /// Characters translated before normal-mode keys are looked up, so that
+    /// commands work from a non-Latin keyboard layout.
+    pub langmap: HashMap<char, char>,
+    /// A template replacing the default statusline; empty for the default.
+    pub statusline: Vec<statusline::Item>,
+    /// Screen columns between tab stops.
+    pub tabstop: usize,
+    /// Columns per indent level, which is where indent guides are drawn.
+    pub shiftwidth: usize,
+    /// Draw vertical guides in the leading whitespace of each line.
+    pub indent_guides: bool,
+    pub indent_guide_char: char,
+    /// Show line numbers in a gutter left of the text.
+    pub number: bool,
+    /// Number lines by their distance from the cursor line. Combined with
+    /// `number`, the cursor line shows its own number.
+    pub relative_number: bool,
+    /// Set the terminal title to the file being edited.
+    pub title: bool,
+    /// A `statusline`-style template for the title; empty for the default.
+    pub titlestring: Vec<statusline::Item>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            timeoutlen: 1000,
+            ttimeoutlen: 50,
+            cursor_normal: SetCursorStyle::SteadyBlock,
+            cursor_insert: SetCursorStyle::BlinkingBar,
+            cursor_command: SetCursorStyle::SteadyBar,
+            root_markers: [
+                ".git",
+                ".hg",
+                ".svn",
+                "Cargo.toml",
+                "package.json",
+                "go.mod",
+            ]
+            .map(String::from)
+            .to_vec(),
+            langmap: HashMap::new(),
+            statusline: Vec::new(),
+            tabstop: 8,
+            shiftwidth: 4,
+            indent_guides: false,
+            indent_guide_char: '│',
+            number: false,
+            relative_number: false,
+            title: true,
+            titlestring: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Applies a single argument of `set`: `name=value`, or `name` and
+    /// `noname` to switch a flag on and off.
+    pub fn set(&mut self, arg: &str) -> Result<()> {
+        let Some((name, value)) = arg.split_once('=') else {
+            return self.set_flag(arg);
+        };
+        match name {
+            "timeoutlen" | "tm" => self.timeoutlen = parse_number(name, value)?,
+            "ttimeoutlen" | "ttm" => self.ttimeoutlen = parse_number(name, value)?,
+            "cursorshape" => self.set_cursor_shapes(value)?,
+            "rootmarkers" => self.root_markers = value.split(',').map(String::from).collect(),
+            "langmap" | "lmap" => self.langmap = parse_langmap(value)?,
+            "statusline" | "stl" => self.statusline = statusline::parse(value)?,
+            "titlestring" => self.titlestring = statusline::parse(value)?,
+            "tabstop" | "ts" => self.tabstop = parse_positive(name, value)?,
+            "shiftwidth" | "sw" => self.shiftwidth = parse_positive(name, value)?,
+            "indentguidechar" => {
+                let mut chars = value.chars();
+                self.indent_guide_char = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => bail!("indentguidechar expects one character, got {value:?}"),
+                };
+            }
+            _ => bail!("unknown option: {name}"),
+        }
+        Ok(())
+    }
+
+    fn set_flag(&mut self, arg: &str) -> Result<()> {
+        let (name, on) = match arg.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        match name {
+            "indentguides" | "ig" => self.indent_guides = on,
+            "number" | "nu" => self.number = on,
+            "relativenumber" | "rnu" => self.relative_number = on,
+            "title" => self.title = on,
+            _ => bail!("expected name=value or a flag, got {arg:?}"),
+        }
+        Ok(())
+    }
+
+    pub fn cursor_shape(&self, mode: Mode) -> SetCursorStyle {
+        match mode {
+            Mode::Normal => self.cursor_normal,
+            Mode::Insert => self.cursor_insert,
+            Mode::Command => self.cursor_command,
+        }
+    }
+
+    /// Parses comma-separated `modes:shape` entries, where modes are `n`, `i`
+    /// and `c` joined by `-` and shape is `block`, `bar` or `underline`,
+    /// optionally followed by `-blink`.
+    fn set_cursor_shapes(&mut self, value: &str) -> Result<()> {
+        for entry in value.split(',') {
+            let (modes, shape) = entry
+                .split_once(':')
+                .ok_or_else(|| anyhow!("expected modes:shape, got {entry:?}"))?;
+            let style = match shape {
+                "block" => SetCursorStyle::SteadyBlock,
+                "block-blink" => SetCursorStyle::BlinkingBlock,
+                "bar" => SetCursorStyle::SteadyBar,
+                "bar-blink" => SetCursorStyle::BlinkingBar,
+                "underline" => SetCursorStyle::SteadyUnderScore,
+                "underline-blink" => SetCursorStyle::BlinkingUnderScore,
+                _ => bail!("unknown cursor shape: {shape}"),
+            };
+            for mode in modes.split('-') {
+                match mode {
+                    "n" => self.cursor_normal = style,
+                    "i" => self.cursor_insert = style,
+                    "c" => self.cursor_command = style,
+                    _ => bail!("unknown mode in cursorshape: {mode}"),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64> {
+    value
+        .parse()
+        .with_context(|| format!("{name} expects a number, got {value:?}"))
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize> {
+    match value.parse() {
+        Ok(0) | Err(_) => bail!("{name} expects a positive number, got {value:?}"),
+        Ok(n) => Ok(n),
+    }
+}
+
+/// Parses vim's langmap format: comma-separated parts that are either
+/// `from;to` with equally long character lists, or pairs such as `фaиb`.
+/// A backslash escapes `,`, `;` and itself.
+fn parse_langmap(value: &str) -> Result<HashMap<char, char>> {
+    let mut map = HashMap::new();
+    let mut parts = vec![(Vec::new(), None::<Vec<char>>)];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let (from, to) = parts.last_mut().expect("parts is never empty");
+        let c = match c {
+            ',' => {
+                parts.push((Vec::new(), None));
+                continue;
+            }
+            ';' if to.is_none() => {
+                *to = Some(Vec::new());
+                continue;
+            }
+            '\\' => chars.next().ok_or_else(|| anyhow!("trailing backslash"))?,
+            c => c,
+        };
+        to.as_mut().unwrap_or(from).push(c);
+    }
+    for (from, to) in parts {
+        match to {
+            Some(to) if to.len() == from.len() => map.extend(from.into_iter().zip(to)),
+            Some(_) => bail!("langmap: both sides of ; must be the same length"),
+            None if from.len() % 2 == 0 => {
+                map.extend(from.chunks(2).map(|pair| (pair[0], pair[1])))
+            }
+            None => bail!(
+                "langmap: odd number of characters in {:?}",
+                String::from_iter(from)
+            ),
+        }
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn langmap_pairs_and_lists() {
+        let map = parse_langmap("jk;kj,фa").unwrap();
+        assert_eq!(map.get(&'j'), Some(&'k'));
+        assert_eq!(map.get(&'k'), Some(&'j'));
+        assert_eq!(map.get(&'ф'), Some(&'a'));
+    }
+
+    #[test]
+    fn langmap_escapes() {
+        let map = parse_langmap(r"\,\;;ab").unwrap();
+        assert_eq!(map.get(&','), Some(&'a'));
+        assert_eq!(map.get(&';'), Some(&'b'));
+    }
+
+    #[test]
+    fn langmap_errors() {
+        assert!(parse_langmap("abc").is_err());
+        assert!(parse_langmap("ab;c").is_err());
+        assert!(parse_langmap("a\\").is_err());
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::{Path, PathBuf};
+
+/// Walks up from `start` to the nearest directory containing one of
+/// `markers`, such as `.git` or `Cargo.toml`.
+pub fn find_root(start: &Path, markers: &[String]) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| markers.iter().any(|marker| dir.join(marker).exists()))
+        .map(Path::to_path_buf)
+}
+
+/// Shows `cwd` as `project/relative/path` inside a project, and relative to
+/// the home directory as `~/path` outside of one.
+pub fn display_cwd(cwd: &Path, root: Option<&Path>) -> String {
+    if let Some(root) = root {
+        if let Ok(relative) = cwd.strip_prefix(root) {
+            let name = root.file_name().map_or_else(
+                || root.display().to_string(),
+                |n| n.to_string_lossy().into(),
+            );
+            return if relative.as_os_str().is_empty() {
+                name
+            } else {
+                Path::new(&name).join(relative).display().to_string()
+            };
+        }
+    }
+    match dirs::home_dir().and_then(|home| cwd.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) => Path::new("~").join(relative).display().to_string(),
+        None => cwd.display().to_string(),
+    }
+}
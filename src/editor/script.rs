@@ -0,0 +1,62 @@
+use std::{fs::File, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use crossterm::event::KeyCode;
+
+use super::{
+    keymap::{parse_keys, Key, Typed},
+    Editor,
+};
+
+impl Editor {
+    /// Queues the keys in `path`, written in the same notation as mappings,
+    /// to be run as if typed before any keys from the terminal. Line breaks
+    /// are ignored; Enter is `<CR>`.
+    pub fn source_script(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("can't read script {}", path.display()))?;
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let keys = parse_keys(line).with_context(|| format!("in {}", path.display()))?;
+            self.typeahead.extend(keys.into_iter().map(Typed::new));
+        }
+        Ok(())
+    }
+
+    /// Writes every key typed from now on to `path`, replacing what was
+    /// there, in a form `source_script` can replay.
+    pub fn record_script(&mut self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("can't create script {}", path.display()))?;
+        self.script_out = Some(file);
+        Ok(())
+    }
+
+    pub(super) fn record_key(&mut self, key: &Key) -> Result<()> {
+        if let Some(file) = &mut self.script_out {
+            write!(file, "{key}")?;
+            // One line per Enter keeps the script readable.
+            if matches!(key, Key::Code(KeyCode::Enter, _)) {
+                writeln!(file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a paste as `<Plug>(paste text)`, so that replaying it inserts
+    /// the text literally rather than typing it through mappings and
+    /// insert-mode keys. `\`, `)` and line breaks are escaped with a
+    /// backslash to keep the text on one line of the script.
+    pub(super) fn record_paste(&mut self, text: &str) -> Result<()> {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                ')' => escaped.push_str("\\)"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        self.record_key(&Key::Plug(format!("paste {escaped}")))
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::{bail, Result};
+
+/// A part of a `statusline` template, which is parsed once when the option
+/// is set and rendered on every redraw.
+#[derive(Debug, Clone)]
+pub enum Item {
+    Text(String),
+    Field(Field),
+    /// `%#Group#`: draw what follows in `Group`.
+    Highlight(String),
+    /// `%=`: right-align what follows.
+    Align,
+    /// `%( ... %)`: left out when it has fields and all of them are empty.
+    Group(Vec<Item>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Field {
+    /// `%M`
+    Mode,
+    /// `%d`, shown as in the default statusline.
+    Directory,
+    /// `%f`: the file relative to the working directory, empty for none.
+    File,
+    /// `%m`: `[+]` when the buffer has unsaved changes.
+    Modified,
+    /// `%l`
+    Line,
+    /// `%c`
+    Column,
+    /// `%p`: how far through the buffer the cursor line is, in percent.
+    Percent,
+}
+
+/// Parses a template such as `%M %d%=%l:%c`. `%%` is a literal `%`.
+pub fn parse(template: &str) -> Result<Vec<Item>> {
+    let mut stack = vec![Vec::new()];
+    let mut text = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        let code = match chars.next() {
+            Some('%') => {
+                text.push('%');
+                continue;
+            }
+            Some(code) => code,
+            None => bail!("trailing % in statusline"),
+        };
+        let items = stack.last_mut().expect("stack is never empty");
+        if !text.is_empty() {
+            items.push(Item::Text(std::mem::take(&mut text)));
+        }
+        match code {
+            'M' => items.push(Item::Field(Field::Mode)),
+            'd' => items.push(Item::Field(Field::Directory)),
+            'f' => items.push(Item::Field(Field::File)),
+            'm' => items.push(Item::Field(Field::Modified)),
+            'l' => items.push(Item::Field(Field::Line)),
+            'c' => items.push(Item::Field(Field::Column)),
+            'p' => items.push(Item::Field(Field::Percent)),
+            'y' => bail!("%y: there are no filetypes yet"),
+            '=' => items.push(Item::Align),
+            '#' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('#') if !name.is_empty() => break,
+                        Some(c) if c.is_alphanumeric() || c == '_' => name.push(c),
+                        _ => bail!("expected %#Group# in statusline"),
+                    }
+                }
+                items.push(Item::Highlight(name));
+            }
+            '(' => stack.push(Vec::new()),
+            ')' => {
+                if stack.len() == 1 {
+                    bail!("%) without %( in statusline");
+                }
+                let group = stack.pop().expect("checked above");
+                stack
+                    .last_mut()
+                    .expect("stack is never empty")
+                    .push(Item::Group(group));
+            }
+            _ => bail!("unknown statusline item: %{code}"),
+        }
+    }
+    if stack.len() > 1 {
+        bail!("%( without %) in statusline");
+    }
+    let mut items = stack.pop().expect("stack is never empty");
+    if !text.is_empty() {
+        items.push(Item::Text(text));
+    }
+    Ok(items)
+}
+
+/// Text runs paired with the highlight group to draw them in.
+#[derive(Default)]
+pub struct Rendered {
+    pub left: Vec<(String, String)>,
+    /// Everything after `%=`.
+    pub right: Vec<(String, String)>,
+}
+
+pub fn render(items: &[Item], value: &dyn Fn(Field) -> String) -> Rendered {
+    let mut renderer = Renderer {
+        out: Rendered::default(),
+        group: "StatusLine".to_string(),
+        aligned: false,
+        fields: 0,
+        filled: 0,
+        value,
+    };
+    renderer.items(items);
+    renderer.out
+}
+
+struct Renderer<'a> {
+    out: Rendered,
+    group: String,
+    aligned: bool,
+    /// Fields rendered so far, and how many of them weren't empty.
+    fields: usize,
+    filled: usize,
+    value: &'a dyn Fn(Field) -> String,
+}
+
+impl Renderer<'_> {
+    fn items(&mut self, items: &[Item]) {
+        for item in items {
+            match item {
+                Item::Text(text) => self.push(text.clone()),
+                Item::Field(field) => {
+                    let text = (self.value)(*field);
+                    self.fields += 1;
+                    if !text.is_empty() {
+                        self.filled += 1;
+                    }
+                    self.push(text);
+                }
+                Item::Highlight(group) => self.group = group.clone(),
+                Item::Align => self.aligned = true,
+                Item::Group(items) => {
+                    let saved = (
+                        self.out.left.len(),
+                        self.out.right.len(),
+                        self.group.clone(),
+                        self.aligned,
+                    );
+                    let (fields, filled) = (self.fields, self.filled);
+                    self.items(items);
+                    if self.fields > fields && self.filled == filled {
+                        self.out.left.truncate(saved.0);
+                        self.out.right.truncate(saved.1);
+                        self.group = saved.2;
+                        self.aligned = saved.3;
+                    }
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        let runs = if self.aligned {
+            &mut self.out.right
+        } else {
+            &mut self.out.left
+        };
+        runs.push((self.group.clone(), text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A highlight group and the text drawn in it.
+    type Run = (String, String);
+
+    fn value(field: Field) -> String {
+        match field {
+            Field::Mode => "NORMAL",
+            Field::Directory => "~/src",
+            Field::File => "main.rs",
+            Field::Modified => "",
+            Field::Line => "3",
+            Field::Column => "7",
+            Field::Percent => "50",
+        }
+        .to_string()
+    }
+
+    fn render_str(template: &str) -> (Vec<Run>, Vec<Run>) {
+        let rendered = render(&parse(template).unwrap(), &value);
+        (rendered.left, rendered.right)
+    }
+
+    fn runs(runs: &[(&str, &str)]) -> Vec<Run> {
+        runs.iter()
+            .map(|(group, text)| (group.to_string(), text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn fields_and_literal_percent() {
+        let (left, right) = render_str("%M %f %l:%c %p%%");
+        let text: String = left.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(text, "NORMAL main.rs 3:7 50%");
+        assert!(right.is_empty());
+    }
+
+    #[test]
+    fn align_splits_left_and_right() {
+        let (left, right) = render_str("%f%=%l");
+        assert_eq!(left, runs(&[("StatusLine", "main.rs")]));
+        assert_eq!(right, runs(&[("StatusLine", "3")]));
+    }
+
+    #[test]
+    fn highlight_applies_to_what_follows() {
+        let (left, _) = render_str("a%#Mode#b");
+        assert_eq!(left, runs(&[("StatusLine", "a"), ("Mode", "b")]));
+    }
+
+    #[test]
+    fn group_is_dropped_when_all_fields_are_empty() {
+        let (left, _) = render_str("%f%( [%m]%)");
+        assert_eq!(left, runs(&[("StatusLine", "main.rs")]));
+        let (left, _) = render_str("%f%( [%m%l]%)");
+        let text: String = left.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(text, "main.rs [3]");
+        // Without fields, a group is kept.
+        let (left, _) = render_str("%(x%)");
+        assert_eq!(left, runs(&[("StatusLine", "x")]));
+    }
+
+    #[test]
+    fn dropped_group_restores_highlight_and_alignment() {
+        let (left, right) = render_str("%(%#Mode#%=%m%)x");
+        assert_eq!(left, runs(&[("StatusLine", "x")]));
+        assert!(right.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        for template in ["%", "%q", "%y", "%#Mode", "%##", "%(a", "a%)"] {
+            assert!(parse(template).is_err(), "{template}");
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+
+use anyhow::Result;
+use crossterm::{terminal::SetTitle, Command, QueueableCommand};
+
+use super::{indent, modified_flag, statusline, Editor};
+
+impl Editor {
+    /// Sets the terminal title from the `titlestring` option, or to the file
+    /// name and modified flag by default. Only written when it changes, since
+    /// some multiplexers redraw their tab bar on every title update.
+    pub(super) fn update_title(&mut self) -> Result<()> {
+        if !self.caps.title || !self.options.title {
+            return Ok(());
+        }
+        let title = if self.options.titlestring.is_empty() {
+            let mut title = self.file_name().unwrap_or_else(|| "[No Name]".to_string());
+            if self.buffer.modified() {
+                title = format!("{title} {}", modified_flag(true));
+            }
+            format!("{title} - muelsyse")
+        } else {
+            let rendered = statusline::render(&self.options.titlestring, &|field| {
+                self.statusline_field(field)
+            });
+            let title: String = rendered
+                .left
+                .iter()
+                .chain(&rendered.right)
+                .map(|(_, text)| text.as_str())
+                .collect();
+            // Literal text in the template could end the title sequence.
+            indent::escape_controls(&title).into_owned()
+        };
+        if self.title.as_ref() != Some(&title) {
+            self.stdout.queue(SetTitle(&title))?;
+            self.title = Some(title);
+        }
+        Ok(())
+    }
+}
+
+/// Saves the terminal's title on its title stack (XTWINOPS 22), so that it
+/// can be put back on exit with `PopTitle`.
+pub struct PushTitle;
+
+/// Restores the title saved by `PushTitle` (XTWINOPS 23).
+pub struct PopTitle;
+
+impl Command for PushTitle {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        f.write_str("\x1b[22;0t")
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Command for PopTitle {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        f.write_str("\x1b[23;0t")
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
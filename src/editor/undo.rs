@@ -0,0 +1,133 @@
+/// A change to a buffer that can be reverted by its opposite. Text may span
+/// lines, joined with `\n`.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Insert { at: (usize, usize), text: String },
+    Delete { at: (usize, usize), text: String },
+}
+
+impl Edit {
+    /// Where the edit starts, which is where the cursor goes when it is
+    /// undone or redone.
+    pub fn position(&self) -> (usize, usize) {
+        match self {
+            Edit::Insert { at, .. } | Edit::Delete { at, .. } => *at,
+        }
+    }
+}
+
+/// Undo and redo stacks of edit groups. Edits collect in the current group
+/// until it is committed, so a whole insert session undoes in one step.
+#[derive(Debug)]
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    current: Vec<Edit>,
+    /// Depth of the undo stack when the buffer was last saved, or `None` if
+    /// that state can no longer be reached.
+    saved: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            current: Vec::new(),
+            saved: Some(0),
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit) {
+        self.current.push(edit);
+    }
+
+    /// Closes the current group. A new change forgets what could be redone.
+    pub fn commit(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        self.undo.push(std::mem::take(&mut self.current));
+        if self.saved.is_some_and(|saved| saved >= self.undo.len()) {
+            self.saved = None;
+        }
+        self.redo.clear();
+    }
+
+    /// The group to revert, in the order it was made.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        self.commit();
+        let group = self.undo.pop()?;
+        self.redo.push(group.clone());
+        Some(group)
+    }
+
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        self.commit();
+        let group = self.redo.pop()?;
+        self.undo.push(group.clone());
+        Some(group)
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.commit();
+        self.saved = Some(self.undo.len());
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.current.is_empty() && self.saved == Some(self.undo.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize) -> Edit {
+        Edit::Insert {
+            at: (0, x),
+            text: "a".to_string(),
+        }
+    }
+
+    #[test]
+    fn edits_group_until_commit() {
+        let mut history = History::default();
+        history.record(insert(0));
+        history.record(insert(1));
+        history.commit();
+        history.record(insert(2));
+        let group = history.undo().unwrap();
+        assert_eq!(group.len(), 1);
+        let group = history.undo().unwrap();
+        assert_eq!(
+            group.iter().map(Edit::position).collect::<Vec<_>>(),
+            [(0, 0), (0, 1)]
+        );
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn new_change_clears_redo() {
+        let mut history = History::default();
+        history.record(insert(0));
+        history.undo();
+        history.record(insert(1));
+        history.commit();
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn empty_commit_keeps_redo_and_saved() {
+        let mut history = History::default();
+        history.record(insert(0));
+        history.mark_saved();
+        history.undo();
+        history.commit();
+        assert!(!history.is_saved());
+        assert!(history.redo().is_some());
+        assert!(history.is_saved());
+    }
+}
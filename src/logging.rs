@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding the log filter, e.g. `MUELSYSE_LOG=debug`.
+pub const LOG_ENV: &str = "MUELSYSE_LOG";
+const LOG_FILE: &str = "muelsyse.log";
+const MAX_LOG_FILES: usize = 7;
+
+pub fn log_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("muelsyse")
+}
+
+/// Installs a daily-rotating file logger. `level` comes from the command line
+/// and takes precedence over `MUELSYSE_LOG`; both accept `EnvFilter` syntax.
+///
+/// The returned guard flushes pending lines when dropped, so keep it alive
+/// until the editor exits. Only an invalid `level` is an error: when the log
+/// directory can't be created, e.g. with a read-only home, the editor runs
+/// without logging and `None` is returned.
+pub fn init(level: Option<&str>) -> Result<Option<WorkerGuard>> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let dir = log_dir();
+    let Ok(appender) = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE)
+        .max_log_files(MAX_LOG_FILES)
+        .build(&dir)
+    else {
+        return Ok(None);
+    };
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(false)
+        .init();
+    Ok(Some(guard))
+}
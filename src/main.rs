@@ -1,7 +1,68 @@
-use anyhow::Result;
+use std::{path::PathBuf, process::ExitCode};
+
+use anyhow::{bail, Result};
 
 mod editor;
+mod logging;
+
+#[derive(Default)]
+struct Args {
+    log_level: Option<String>,
+    no_altscreen: bool,
+    script_in: Option<PathBuf>,
+    script_out: Option<PathBuf>,
+    file: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self::default();
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--log-level" => match iter.next() {
+                    Some(level) => args.log_level = Some(level),
+                    None => bail!("--log-level requires a value"),
+                },
+                "--no-altscreen" => args.no_altscreen = true,
+                "-s" => match iter.next() {
+                    Some(path) => args.script_in = Some(path.into()),
+                    None => bail!("-s requires a script file"),
+                },
+                "-w" => match iter.next() {
+                    Some(path) => args.script_out = Some(path.into()),
+                    None => bail!("-w requires a script file"),
+                },
+                _ if !arg.starts_with('-') && args.file.is_none() => args.file = Some(arg.into()),
+                _ => bail!("unknown argument: {arg}"),
+            }
+        }
+        Ok(args)
+    }
+}
 
-fn main() -> Result<()> {
-    editor::Editor::new().run()
+/// Exits with 0 on a normal quit and 1 when the editor is aborted. An error
+/// returned from here is printed and exits with 1 as well.
+fn main() -> Result<ExitCode> {
+    let args = Args::parse()?;
+    let _guard = logging::init(args.log_level.as_deref())?;
+    tracing::info!("starting muelsyse");
+    let mut editor = editor::Editor::new();
+    if args.no_altscreen {
+        editor.disable_alternate_screen();
+    }
+    if let Some(path) = &args.file {
+        editor.open(path)?;
+    }
+    if let Some(path) = &args.script_in {
+        editor.source_script(path)?;
+    }
+    if let Some(path) = &args.script_out {
+        editor.record_script(path)?;
+    }
+    let result = editor.run();
+    if let Err(e) = &result {
+        tracing::error!("editor exited with error: {e:#}");
+    }
+    result
 }